@@ -1,5 +1,5 @@
 use gaps_rs::opt;
-use gaps_rs::Stats;
+use gaps_rs::{Stats, GapsError};
 use gaps_rs::build_tree;
 use structopt::StructOpt;
 use std::fs;
@@ -8,47 +8,87 @@ use std::io::Write;
 
 fn main() {
     let opt = opt::Benchmark::from_args();
+
+    if let Err(e) = benchmark(opt) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn benchmark(opt: opt::Benchmark) -> Result<(), GapsError> {
     let mut gaps_opt = opt::Gaps {
         infile: "replaced in loop".to_string(),
         fastafile: opt.fastafile,
         outfile: "will get replaced anyway".to_string(),
         format: opt.format,
+        weights: "off".to_string(),
+        coding: "unordered".to_string(),
         pattern: opt.pattern,
+        pattern_file: opt.pattern_file,
+        random_patterns: opt.random_patterns,
+        pattern_weight: opt.pattern_weight,
+        pattern_length: opt.pattern_length,
+        pattern_seed: opt.pattern_seed,
         range: opt.range,
+        range_upstream: opt.range,
+        direction: "downstream".to_string(),
+        matcher: "sorted".to_string(),
+        match_policy: "first".to_string(),
+        pairs_per_block: 1,
+        mismatches: 0,
+        min_score: None,
+        min_dont_care_score: None,
+        min_anchor_score: None,
+        anchor_pattern: None,
+        soft_mask: false,
+        dust: false,
+        dust_window: 64,
+        dust_threshold: 20.0,
+        verify: false,
+        verify_pattern: None,
+        drop_mismatches: false,
+        min_indel: None,
+        max_indel: None,
+        locus_policy: "keep".to_string(),
         all: opt.all,
         weak: opt.weak,
         hide_progress: opt.hide_progress,
-        print_pairs: false
+        print_pairs: false,
+        stream: false,
+        chunk_size: 100000
     };
 
     let mut stats = Vec::new();
-    for infile in fs::read_dir(opt.infolder).unwrap() {
-        gaps_opt.infile = infile.unwrap().path().to_str().unwrap().to_string();
-        stats.push(get_stats(gaps_opt.clone(), &opt.nwkfile));
+    let infolder = &opt.infolder;
+    for infile in fs::read_dir(infolder).map_err(|e| GapsError::io(infolder, e))? {
+        let infile = infile.map_err(|e| GapsError::io(infolder, e))?;
+        gaps_opt.infile = infile.path().to_string_lossy().into_owned();
+        stats.push(get_stats(gaps_opt.clone(), &opt.nwkfile)?);
     }
 
-    let mut f = File::create(opt.outfile)
-        .expect("Unable to create file");
+    let outfile = &opt.outfile;
+    let mut f = File::create(outfile)
+        .map_err(|e| GapsError::io(outfile, e))?;
     f.write_all(Stats::stats_to_csv(&stats, ",").as_bytes())
-        .expect("Unable to write data");
+        .map_err(|e| GapsError::io(outfile, e))
 }
 
 // runs gaps, nwk and rfdist binaries and returns a stats struct (with valid rfdist)
-fn get_stats(mut opt: opt::Gaps, nwk_file: &str) -> Stats {
+fn get_stats(mut opt: opt::Gaps, nwk_file: &str) -> Result<Stats, GapsError> {
     // Create temporary stuff
-    let tmp_dir = gaps_rs::tools::create_tmp_folder();
+    let tmp_dir = gaps_rs::tools::create_tmp_folder()?;
     let mut tmp_outfile = tmp_dir.clone();
     tmp_outfile.push("outfile");
     let mut tmp_intree = tmp_dir.clone();
     tmp_intree.push("intree");
 
     // run gaps
-    opt.outfile = tmp_outfile.to_str().unwrap().to_string();
-    let mut stats = gaps_rs::run(opt.clone()).unwrap();
+    opt.outfile = tmp_outfile.to_string_lossy().into_owned();
+    let mut stats = gaps_rs::run(opt.clone())?;
 
     // run qcheck if format is max-cut
     if opt.format == "max-cut" {
-        let correct = gaps_rs::tools::qcheck(&opt.outfile, &opt.fastafile, nwk_file);
+        let correct = gaps_rs::tools::qcheck(&opt.outfile, &opt.fastafile, nwk_file)?;
         stats.correct_perc = correct.1 as f64 / (correct.1 as f64 + correct.0 as f64) * 100.0;
     }
 
@@ -56,20 +96,20 @@ fn get_stats(mut opt: opt::Gaps, nwk_file: &str) -> Stats {
     let tree = match &opt.format[..] {
         "max-cut" => build_tree::max_cut_from_file(&opt.outfile),
         "paup" => build_tree::pars(opt::Nwk { method: opt.format.clone(), infile: opt.outfile.clone(), verbose: false, all: false }),
-        "phylip" => panic!("phylip pars not yet supported"),
-        _ => panic!("This shouldn't happen, because structopt catches invalid inputs")
-    };
+        format => Err(GapsError::Invalid(format!("Trees can't be built from the format \"{}\" yet", format)))
+    }?;
 
     // prepare intree file
-    fs::copy(nwk_file, &tmp_intree).unwrap();
-    let mut file = fs::OpenOptions::new().append(true).open(&tmp_intree).unwrap();
-    file.write_all(tree.as_bytes()).unwrap();
+    let intree = tmp_intree.to_string_lossy().into_owned();
+    fs::copy(nwk_file, &tmp_intree).map_err(|e| GapsError::io(nwk_file, e))?;
+    let mut file = fs::OpenOptions::new().append(true).open(&tmp_intree).map_err(|e| GapsError::io(&intree, e))?;
+    file.write_all(tree.as_bytes()).map_err(|e| GapsError::io(&intree, e))?;
 
     // get rf distance
-    stats.rfdist = gaps_rs::tools::rfdist(tmp_intree.to_str().unwrap()) as i64;
+    stats.rfdist = gaps_rs::tools::rfdist(&intree)? as i64;
 
     // remove tmp dir
-    fs::remove_dir_all(tmp_dir).unwrap();
+    fs::remove_dir_all(&tmp_dir).map_err(|e| GapsError::io(&tmp_dir.to_string_lossy(), e))?;
 
-    stats
+    Ok(stats)
 }
\ No newline at end of file
@@ -3,6 +3,11 @@ use structopt::StructOpt;
 
 fn main() {
 	let opt = Gaps::from_args();
-	let stats = gaps_rs::run(opt).unwrap();
-	println!("{}", stats);
-}
\ No newline at end of file
+	match gaps_rs::run(opt) {
+		Ok(stats) => println!("{}", stats),
+		Err(e) => {
+			eprintln!("Error: {}", e);
+			std::process::exit(1);
+		}
+	}
+}
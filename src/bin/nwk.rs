@@ -1,4 +1,4 @@
-use gaps_rs::{opt::Nwk, build_tree::{pars, max_cut_from_file}};
+use gaps_rs::{GapsError, opt::Nwk, build_tree::{pars, max_cut_from_file}};
 use structopt::StructOpt;
 
 fn main() {
@@ -7,9 +7,14 @@ fn main() {
 	let result = match &opt.method[..] {
 		"max-cut" => max_cut_from_file(&opt.infile),
 		"paup" => pars(opt),
-		"phylip" => panic!("phylip pars not yet supported"),
-		_ => panic!("This shouldn't happen, because structopt catches invalid inputs")
+		method => Err(GapsError::Invalid(format!("Trees can't be built with the method \"{}\" yet", method)))
 	};
 
-	println!("{}", result);
-}
\ No newline at end of file
+	match result {
+		Ok(tree) => println!("{}", tree),
+		Err(e) => {
+			eprintln!("Error: {}", e);
+			std::process::exit(1);
+		}
+	}
+}
@@ -1,8 +1,31 @@
 use gaps_rs::opt::Rfdist;
-use gaps_rs::tools;
+use gaps_rs::{tools, GapsError};
 use structopt::StructOpt;
 
 fn main() {
 	let opt = Rfdist::from_args();
-	println!("{}", tools::rfdist(&opt.infile));
-}
\ No newline at end of file
+
+	if let Err(e) = rfdist(opt) {
+		eprintln!("Error: {}", e);
+		std::process::exit(1);
+	}
+}
+
+fn rfdist(opt: Rfdist) -> Result<(), GapsError> {
+	if opt.phylip {
+		let native = tools::rfdist(&opt.infile)?;
+		let phylip = tools::rfdist_phylip(&opt.infile)?;
+		if native != phylip {
+			eprintln!("Warning: RF distance differs from phylip treedist ({} vs. {})", native, phylip);
+		}
+	}
+
+	if opt.normalized {
+		println!("{}", tools::rfdist_normalized(&opt.infile)?);
+	}
+	else {
+		println!("{}", tools::rfdist(&opt.infile)?);
+	}
+
+	Ok(())
+}
@@ -1,125 +1,254 @@
-use crate::{tools, opt};
+use crate::{tools, opt, Quartet, GapsError};
 use std::collections::HashMap;
-use std::io::{Write, BufRead, BufReader};
+use std::io::{BufRead, BufReader};
 use std::fs::{self, File};
 use std::process::{Command, Stdio};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
 
 // === max-cut =====================================================================
 
-pub fn max_cut_from_file(filename: &str) -> String {
-	let f = File::open(filename).expect("Unable to open file");
+// Accepts quartets in Newick notation (one per line) or in max-cut notation with weights
+pub fn max_cut_from_file(filename: &str) -> Result<String, GapsError> {
+	let f = File::open(filename).map_err(|e| GapsError::io(filename, e))?;
 	let r = BufReader::new(f);
 
-	let mut trees = Vec::new();
+	let mut quartets = Vec::new();
 
-	for line in r.lines() {
-		trees.push(line.unwrap());
+	for (i, line) in r.lines().enumerate() {
+		let line = line.map_err(|e| GapsError::io(filename, e))?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		quartets.push(Quartet::parse(&line).map_err(|e| e.in_file(filename, Some(i + 1)))?);
 	}
 
-	max_cut(&trees)
+	Ok(max_cut(&quartets))
 }
 
-pub fn max_cut(qtrees: &[String]) -> String {
+pub fn max_cut(quartets: &[Quartet]) -> String {
 	// Replace species names by ids
-	let id_dict = build_id_dict(&qtrees);
-	let qtrees = replace_names_to_ids(qtrees, &id_dict);
-
-	// Write reformatted QTrees to temp file
-	let mut f = File::create("max_cut_input.tmp").expect("Unable to create file");
-	for tree in qtrees {
-		f.write_all(format!("{}\n", to_max_cut_string(&tree)).as_bytes()).expect("Unable to write data");
-	}
-
-	// Run max-cut
-	Command::new("max-cut-tree")
-				.arg("qrtt=max_cut_input.tmp")
-				.arg("weights=off")
-				.arg("otre=max_cut_output.tmp")
-				.output()
-				.expect("failed to execute max-cut");
-
-	// Read and reformat result
-	let nwk = replace_ids_to_names(&fs::read_to_string("max_cut_output.tmp").unwrap().replace("\n", ""), &id_dict);
+	let mut names = quartets.iter()
+		.flat_map(|q| q.taxa.iter().cloned())
+		.collect::<Vec<String>>();
+	names.sort_unstable();
+	names.dedup();
+
+	let quartets = quartets.iter()
+		.map(|q| WQuartet {
+			taxa: [0, 1, 2, 3].map(|i| names.binary_search(&q.taxa[i]).unwrap()),
+			weight: q.weight
+		})
+		.collect::<Vec<_>>();
+
+	let taxa = (0..names.len()).collect::<Vec<_>>();
+	let mut next_id = names.len();
+	let mut rng = StdRng::seed_from_u64(0);
+
+	solve(&taxa, &quartets, &mut next_id, &mut rng).to_nwk(&names)
+}
 
-	// Remove tmp files
-	fs::remove_file("max_cut_input.tmp").unwrap();
-	fs::remove_file("max_cut_output.tmp").unwrap();
+// Quartet ab|cd of taxon ids
+#[derive(Debug, Clone)]
+struct WQuartet {
+	taxa: [usize; 4],
+	weight: f64,
+}
 
-	nwk
+// Unrooted tree; taxon ids >= the number of species are artificial taxa
+struct UTree {
+	adj: Vec<Vec<usize>>,
+	taxon: Vec<Option<usize>>,
 }
 
-fn replace_ids_to_names(nwk: &str, dict: &HashMap<String, u32>) -> String {
-	// Invert HashMap
-	let mut dict_inv = Vec::new();
-	dict_inv.resize(dict.len(), "nothing");
+impl UTree {
+	fn star(taxa: &[usize]) -> UTree {
+		let mut adj = vec![(1..=taxa.len()).collect::<Vec<_>>()];
+		let mut taxon = vec![None];
+		for id in taxa {
+			adj.push(vec![0]);
+			taxon.push(Some(*id));
+		}
+		UTree { adj, taxon }
+	}
 
-	for (key, value) in dict {
-		dict_inv[*value as usize] = key;
+	fn leaf(&self, id: usize) -> usize {
+		self.taxon.iter().position(|t| *t == Some(id)).expect("Artificial taxon not found")
 	}
 
-	// Replace ids
-	let mut nwk_new = String::from(nwk);
-	for i in (0..dict_inv.len()).rev() {
-		nwk_new = nwk_new.replace(&format!("({}", i), &format!("(\"{}\"", dict_inv[i]));
-		nwk_new = nwk_new.replace(&format!("{})", i), &format!("\"{}\")", dict_inv[i]));
-		nwk_new = nwk_new.replace(&format!(",{},", i), &format!(",\"{}\",", dict_inv[i]));
+	// Joins both trees by replacing the leaves a and b by an edge between their neighbours
+	fn join(mut self, other: UTree, a: usize, b: usize) -> UTree {
+		let offset = self.adj.len();
+		let leaf_a = self.leaf(a);
+		let leaf_b = other.leaf(b) + offset;
+
+		self.adj.extend(other.adj.into_iter().map(|n| n.into_iter().map(|i| i + offset).collect()));
+		self.taxon.extend(other.taxon);
+
+		let parent_a = self.adj[leaf_a][0];
+		let parent_b = self.adj[leaf_b][0];
+		self.adj[parent_a].retain(|i| *i != leaf_a);
+		self.adj[parent_b].retain(|i| *i != leaf_b);
+		self.adj[parent_a].push(parent_b);
+		self.adj[parent_b].push(parent_a);
+		self.adj[leaf_a].clear();
+		self.adj[leaf_b].clear();
+		self.taxon[leaf_a] = None;
+		self.taxon[leaf_b] = None;
+
+		self
 	}
-	nwk_new = nwk_new.replace("\"", "");
 
-	nwk_new
+	fn to_nwk(&self, names: &[String]) -> String {
+		fn subtree(tree: &UTree, node: usize, parent: usize, names: &[String]) -> String {
+			match tree.taxon[node] {
+				Some(id) => names[id].clone(),
+				None => {
+					let children = tree.adj[node].iter()
+						.filter(|i| **i != parent)
+						.map(|i| subtree(tree, *i, node, names))
+						.collect::<Vec<_>>();
+					format!("({})", children.join(","))
+				}
+			}
+		}
+
+		match self.adj.iter().position(|n| n.len() > 1) {
+			Some(root) => format!("{};", subtree(self, root, root, names)),
+			None => format!("({});", self.taxon.iter().flatten().map(|id| names[*id].clone()).collect::<Vec<_>>().join(","))
+		}
+	}
 }
 
-fn replace_names_to_ids(qtrees: &[String], dict: &HashMap<String, u32>) -> Vec<String> {
-	let mut qtrees_new = Vec::new();
-	
-	for nwk in qtrees {
-		let split = split_nwk(nwk);
-		qtrees_new.push(format!("(({},{}),({},{});", dict[&split[0]], dict[&split[1]], dict[&split[2]], dict[&split[3]]));
+fn solve(taxa: &[usize], quartets: &[WQuartet], next_id: &mut usize, rng: &mut StdRng) -> UTree {
+	if taxa.len() <= 3 {
+		return UTree::star(taxa);
 	}
 
-	qtrees_new
-}
+	let cut = find_cut(taxa, quartets, rng);
+	let side = taxa.iter().cloned().zip(cut).collect::<HashMap<usize, bool>>();
 
-fn build_id_dict(qtrees: &[String]) -> HashMap<String, u32> {
-	let mut dict = HashMap::new();
-	let mut cur_id = 0;
+	// Each side gets an artificial taxon that represents the other side
+	let (a, b) = (*next_id, *next_id + 1);
+	*next_id += 2;
 
-	for tree in qtrees {
-		for name in split_nwk(tree) {
-			if !dict.contains_key(&name) {
-				dict.insert(name, cur_id);
-				cur_id += 1;
-			}
-		}
+	let mut subtrees = Vec::new();
+	for (s, artificial) in [(false, a), (true, b)] {
+		let mut sub_taxa = taxa.iter().filter(|id| side[id] == s).cloned().collect::<Vec<_>>();
+		sub_taxa.push(artificial);
+
+		let sub_quartets = quartets.iter()
+			.filter(|q| q.taxa.iter().filter(|id| side[id] != s).count() <= 1)
+			.map(|q| WQuartet {
+				taxa: q.taxa.map(|id| if side[&id] == s { id } else { artificial }),
+				weight: q.weight
+			})
+			.collect::<Vec<_>>();
+
+		subtrees.push(solve(&sub_taxa, &sub_quartets, next_id, rng));
 	}
 
-	dict
+	let tree_b = subtrees.pop().unwrap();
+	subtrees.pop().unwrap().join(tree_b, a, b)
 }
 
-fn split_nwk(nwk: &str) -> Vec<String> {
-	let mut nwk = String::from(nwk).replace(";", "");
-	nwk = nwk.replace(" ", "");
-	nwk = nwk.replace("),(", ",");
-	nwk = nwk.replace("((", "");
-	nwk = nwk.replace("))", "");
+// Heuristic for the MaxCut approach (Snir and Rao): Find a cut that maximizes the ratio between the
+// weight of "good" edges (between taxa that are separated by a quartet) and "bad" edges (between
+// taxa that are on the same side of a quartet). Both sides need to contain at least two taxa.
+fn find_cut(taxa: &[usize], quartets: &[WQuartet], rng: &mut StdRng) -> Vec<bool> {
+	const RESTARTS: usize = 20;
+
+	let n = taxa.len();
+	let index = taxa.iter().enumerate().map(|(i, id)| (*id, i)).collect::<HashMap<usize, usize>>();
+
+	let mut good = vec![vec![0.0; n]; n];
+	let mut bad = vec![vec![0.0; n]; n];
+	for q in quartets {
+		let t = q.taxa.map(|id| index[&id]);
+		for (x, y) in [(0, 2), (0, 3), (1, 2), (1, 3)] {
+			good[t[x]][t[y]] += q.weight;
+			good[t[y]][t[x]] += q.weight;
+		}
+		for (x, y) in [(0, 1), (2, 3)] {
+			bad[t[x]][t[y]] += q.weight;
+			bad[t[y]][t[x]] += q.weight;
+		}
+	}
+
+	let ratio = |g: f64, b: f64| g / (b + 1e-6);
+	let cut_weight = |m: &Vec<Vec<f64>>, cut: &[bool]| {
+		let mut sum = 0.0;
+		for i in 0..n {
+			for j in i+1..n {
+				if cut[i] != cut[j] {
+					sum += m[i][j];
+				}
+			}
+		}
+		sum
+	};
+
+	// Balanced cut (also used as fallback, if there is no information at all)
+	let initial = (0..n).map(|i| i >= n / 2).collect::<Vec<_>>();
+	let mut best_ratio = ratio(cut_weight(&good, &initial), cut_weight(&bad, &initial));
+	let mut best_cut = initial.clone();
+
+	for _ in 0..RESTARTS {
+		let mut cut = initial.clone();
+		cut.shuffle(rng);
+		let mut g = cut_weight(&good, &cut);
+		let mut b = cut_weight(&bad, &cut);
+		let mut size = cut.iter().filter(|s| **s).count();
+
+		// Local search: move single taxa to the other side as long as this improves the ratio
+		let mut improved = true;
+		while improved {
+			improved = false;
+			for v in 0..n {
+				if (cut[v] && size <= 2) || (!cut[v] && n - size <= 2) {
+					continue;
+				}
+
+				let (mut dg, mut db) = (0.0, 0.0);
+				for u in 0..n {
+					if u == v {
+						continue;
+					}
+					let sign = if cut[u] == cut[v] { 1.0 } else { -1.0 };
+					dg += sign * good[u][v];
+					db += sign * bad[u][v];
+				}
+
+				if ratio(g + dg, b + db) > ratio(g, b) + 1e-9 {
+					g += dg;
+					b += db;
+					size = if cut[v] { size - 1 } else { size + 1 };
+					cut[v] = !cut[v];
+					improved = true;
+				}
+			}
+		}
 
-	let mut result = Vec::new();
-	for name in nwk.split(',') {
-		result.push(String::from(name));
+		if ratio(g, b) > best_ratio + 1e-9 {
+			best_ratio = ratio(g, b);
+			best_cut = cut;
+		}
 	}
-	result
+
+	best_cut
 }
 
-pub fn to_max_cut_string(nwk: &str) -> String {
-	let split = split_nwk(nwk);
-	format!("{},{}|{},{}", split[0], split[1], split[2], split[3])
+pub fn to_max_cut_string(nwk: &str) -> Result<String, GapsError> {
+	let q = Quartet::parse(nwk)?;
+	Ok(format!("{},{}|{},{}", q.taxa[0], q.taxa[1], q.taxa[2], q.taxa[3]))
 }
 
 // === parsimony =============================================================
 
-pub fn pars(opt: opt::Nwk) -> String {
+pub fn pars(opt: opt::Nwk) -> Result<String, GapsError> {
 	// Create temporary folder
-	let tmp_folder = tools::create_tmp_folder();
+	let tmp_folder = tools::create_tmp_folder()?;
 
 	// Some files
 	let mut nex_f = tmp_folder.clone();
@@ -128,7 +257,7 @@ pub fn pars(opt: opt::Nwk) -> String {
 	nwk_f.push("pars.nwk");
 
 	// infile -> tmp/pars.nex
-	fs::copy(opt.infile, nex_f).unwrap();
+	fs::copy(&opt.infile, nex_f).map_err(|e| GapsError::io(&opt.infile, e))?;
 
 	// tmp/pars.nex -> tmp/pars.nwk
 	let stdout = if opt.verbose { Stdio::inherit() } else { Stdio::null() };
@@ -137,23 +266,132 @@ pub fn pars(opt: opt::Nwk) -> String {
 		.arg("-n")
 		.current_dir(&tmp_folder)
 		.stdout(stdout)
-		.spawn().unwrap();
+		.spawn().map_err(|e| GapsError::external_tool("paup", &e.to_string()))?;
 
-	paup.wait().unwrap();
+	paup.wait().map_err(|e| GapsError::external_tool("paup", &e.to_string()))?;
 
 	// Read result file
-	let mut lines = BufReader::new(File::open(nwk_f).expect("Unable to open file")).lines();
+	let lines = fs::read_to_string(&nwk_f)
+		.map_err(|_| GapsError::external_tool("paup", "No trees were saved"))?;
 
 	// Delete temporary folder
-	fs::remove_dir_all(tmp_folder).unwrap();
+	fs::remove_dir_all(&tmp_folder).map_err(|e| GapsError::io(&tmp_folder.to_string_lossy(), e))?;
 
 	// Result
 	if opt.all {
-		lines.map(|s| s.unwrap())
-			.collect::<Vec<String>>()
-			.join("\n")
+		Ok(lines.lines()
+			.collect::<Vec<&str>>()
+			.join("\n"))
 	}
 	else {
-		lines.next().unwrap().unwrap()
+		lines.lines()
+			.next()
+			.map(String::from)
+			.ok_or_else(|| GapsError::external_tool("paup", "No trees were saved"))
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::newick::{self, Tree};
+
+	// All quartets that are induced by a tree with the given splits (each given by one side)
+	fn induced_quartets(taxa: &[&str], splits: &[&[&str]]) -> Vec<Quartet> {
+		let mut result = Vec::new();
+		let n = taxa.len();
+		for i in 0..n {
+			for j in i+1..n {
+				for k in j+1..n {
+					for l in k+1..n {
+						let t = [taxa[i], taxa[j], taxa[k], taxa[l]];
+						for [a, b, c, d] in [[0, 1, 2, 3], [0, 2, 1, 3], [0, 3, 1, 2]] {
+							let separates = |split: &&[&str]| {
+								let side = |x: usize| split.contains(&t[x]);
+								side(a) == side(b) && side(c) == side(d) && side(a) != side(c)
+							};
+							if splits.iter().any(separates) {
+								result.push(Quartet::new([t[a], t[b], t[c], t[d]], 1.0));
+							}
+						}
+					}
+				}
+			}
+		}
+		result
+	}
+
+	#[test]
+	fn induced_quartets_reconstruct_the_tree() {
+		let taxa = ["a", "b", "c", "d", "e", "f"];
+		let expected = Tree::parse("(((a,b),c),(d,(e,f)));").unwrap();
+		let quartets = induced_quartets(&taxa, &[&["a", "b"], &["a", "b", "c"], &["e", "f"]]);
+		assert_eq!(quartets.len(), 15);
+
+		let tree = Tree::parse(&max_cut(&quartets)).unwrap();
+		assert_eq!(tree.leaves(), taxa);
+		assert_eq!(newick::rf_distance(&tree, &expected).unwrap(), 0);
+
+		let expected = Tree::parse("((a,f),(b,e),(c,d));").unwrap();
+		let quartets = induced_quartets(&taxa, &[&["a", "f"], &["b", "e"], &["c", "d"]]);
+		let tree = Tree::parse(&max_cut(&quartets)).unwrap();
+		assert_eq!(newick::rf_distance(&tree, &expected).unwrap(), 0);
+	}
+
+	#[test]
+	fn small_and_empty_inputs() {
+		assert_eq!(max_cut(&[]), "();");
+
+		let names = ["a", "b", "c"].map(String::from);
+		let mut rng = StdRng::seed_from_u64(0);
+		let mut next_id = names.len();
+		assert_eq!(solve(&[0, 1, 2], &[], &mut next_id, &mut rng).to_nwk(&names), "(a,b,c);");
+		assert_eq!(solve(&[0], &[], &mut next_id, &mut rng).to_nwk(&names), "(a);");
+
+		let tree = Tree::parse(&max_cut(&[Quartet::new(["a", "c", "b", "d"], 1.0)])).unwrap();
+		assert_eq!(newick::rf_distance(&tree, &Tree::parse("((a,c),(b,d));").unwrap()).unwrap(), 0);
+	}
+
+	#[test]
+	fn written_quartets_are_read_back() {
+		use crate::{PBlock, QTree, IndelRange, Weighting};
+		use crate::sink::{PairSink, QuartetSink};
+
+		let names = ["A", "B", "C", "D", "E"];
+		let pairs = [
+			PBlock::pair_with_gaps(&names, 0, &[10, 10, 13, 13, 13]),
+			PBlock::pair_with_gaps(&names, 100, &[10, 10, 10, 12, 12]),
+			PBlock::pair_with_gaps(&names, 200, &[10, 10, 13, 13, 13])
+		];
+
+		let tmp_dir = tools::create_tmp_folder().unwrap();
+		let filename = tmp_dir.join("quartets").to_string_lossy().into_owned();
+		let mut sink = QuartetSink::new(&filename, Weighting::Gap, IndelRange::ANY);
+		sink.write_pairs(&pairs).unwrap();
+		sink.finish().unwrap();
+
+		let written = fs::read_to_string(&filename).unwrap().lines()
+			.map(|line| Quartet::parse(line).unwrap())
+			.collect::<Vec<_>>();
+		let quartets = Quartet::aggregate(&QTree::from_pairs(&pairs, IndelRange::ANY).unwrap(), Weighting::Gap);
+		assert_eq!(written, quartets);
+		assert!(written.contains(&Quartet::new(["A", "B", "D", "E"], 8.0)));
+
+		let tree = max_cut_from_file(&filename).unwrap();
+		fs::remove_dir_all(&tmp_dir).unwrap();
+		assert_eq!(tree, max_cut(&quartets));
+		let expected = Tree::parse("((A,B),C,(D,E));").unwrap();
+		assert_eq!(newick::rf_distance(&Tree::parse(&tree).unwrap(), &expected).unwrap(), 0);
+	}
+
+	#[test]
+	fn malformed_quartet_files_report_the_line() {
+		let tmp_dir = tools::create_tmp_folder().unwrap();
+		let filename = tmp_dir.join("quartets").to_string_lossy().into_owned();
+		fs::write(&filename, "a,b|c,d:1\n\na,b|c\n").unwrap();
+
+		let result = max_cut_from_file(&filename);
+		fs::remove_dir_all(&tmp_dir).unwrap();
+		assert!(matches!(result, Err(GapsError::Parse { line: Some(3), .. })));
+	}
+}
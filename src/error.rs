@@ -0,0 +1,59 @@
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum GapsError {
+	/// Reading or writing a file failed
+	Io { file: String, source: io::Error },
+	/// Malformed input file (line numbers start at 1)
+	Parse { file: String, line: Option<usize>, msg: String },
+	/// A sequence referenced by a block is not available
+	MissingSequence { name: String, location: String },
+	/// An external program could not be executed or returned unexpected output
+	ExternalTool { tool: String, msg: String },
+	/// Invalid data that is not tied to a specific file
+	Invalid(String),
+}
+
+impl GapsError {
+	pub fn io(file: &str, source: io::Error) -> GapsError {
+		GapsError::Io { file: file.to_string(), source }
+	}
+
+	pub fn parse(file: &str, line: Option<usize>, msg: &str) -> GapsError {
+		GapsError::Parse { file: file.to_string(), line, msg: msg.to_string() }
+	}
+
+	pub fn external_tool(tool: &str, msg: &str) -> GapsError {
+		GapsError::ExternalTool { tool: tool.to_string(), msg: msg.to_string() }
+	}
+
+	/// Attaches the file (and line) in which invalid data was found
+	pub fn in_file(self, file: &str, line: Option<usize>) -> GapsError {
+		match self {
+			GapsError::Invalid(msg) => GapsError::Parse { file: file.to_string(), line, msg },
+			e => e
+		}
+	}
+}
+
+impl fmt::Display for GapsError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			GapsError::Io { file, source } => write!(f, "{}: {}", file, source),
+			GapsError::Parse { file, line: Some(line), msg } => write!(f, "{}:{}: {}", file, line, msg),
+			GapsError::Parse { file, line: None, msg } => write!(f, "{}: {}", file, msg),
+			GapsError::MissingSequence { name, location } => write!(f, "Sequence \"{}\" not found in {}", name, location),
+			GapsError::ExternalTool { tool, msg } => write!(f, "{} failed: {}", tool, msg),
+			GapsError::Invalid(msg) => write!(f, "{}", msg),
+		}
+	}
+}
+
+impl std::error::Error for GapsError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			GapsError::Io { source, .. } => Some(source),
+			_ => None
+		}
+	}
+}
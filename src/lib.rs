@@ -3,78 +3,139 @@ use rayon::prelude::*;
 use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
 use std::io::Write;
 use std::io::stdout;
+use std::collections::HashMap;
+use crate::sink::PairSink;
+use crate::locus::LocusPolicy;
 
-pub fn run(opt: crate::opt::Gaps) -> Result<Stats, String> {
+pub fn run(opt: crate::opt::Gaps) -> Result<Stats, GapsError> {
 	let time_all = Stopwatch::start_new();
 
 	// =============================================================================================
 
 	if !opt.hide_progress { print!("- Reading FASTA file"); }
 
-	stdout().flush().unwrap();
+	flush_stdout()?;
 	let mut sw = Stopwatch::start_new();
-	let sequences = Sequence::read_fasta_file(&opt.fastafile);
+	let mut sequences = Sequence::read_fasta_file(&opt.fastafile)?;
 
 	if !opt.hide_progress { println!("\t\t(Finished in {}s)\n  => {} input sequences", sw.elapsed_ms() as f32/1000.0, sequences.len()); }
 
+	if opt.soft_mask || opt.dust {
+		sequences.par_iter_mut().for_each(|(_, sequence)| {
+			if opt.soft_mask {
+				sequence.mask_lowercase();
+			}
+			if opt.dust {
+				sequence.mask_low_complexity(opt.dust_window, opt.dust_threshold);
+			}
+		});
+
+		let masked = sequences.values().map(Sequence::masked_len).sum::<usize>();
+		let total = sequences.values().map(Sequence::len).sum::<usize>();
+		if !opt.hide_progress { println!("  => {:.2}% of the bases masked", masked as f64 / total.max(1) as f64 * 100.0); }
+	}
+
 	// =============================================================================================
 
-	if !opt.hide_progress { print!("- Reading PBlock file"); }
+	let patterns = patterns(&opt)?;
+	if let (Some(min), Some(max)) = (opt.min_indel, opt.max_indel) {
+		if min > max {
+			return Err(GapsError::Invalid(format!("Minimum indel length {} is larger than the maximum {}", min, max)));
+		}
+	}
+	if !opt.hide_progress && patterns.len() > 1 { println!("  => {} patterns", patterns.len()); }
 
-	stdout().flush().unwrap();
-	sw.restart();
-	let blocks: Vec<PBlock> = PBlock::read_from_file(&opt.infile);
+	let mut sinks = vec![sink::create(&opt)?];
+	if opt.print_pairs {
+		sinks.push(Box::new(sink::PairsSink::new("pairs.txt")?) as Box<dyn PairSink>);
+	}
+	let mut stats = Stats::new(&[], sequences.len());
+
+	if opt.stream {
+		if opt.locus_policy != "keep" {
+			eprintln!("\nWarning: with --stream, loci are only formed within a chunk of {} blocks; conflicting pairs of a locus that spans several chunks are kept (--locus-policy {})", opt.chunk_size, opt.locus_policy);
+		}
+		if !opt.hide_progress { println!("- Searching for pairs\t\t(in chunks of {} blocks)", opt.chunk_size); }
+
+		flush_stdout()?;
+		sw.restart();
+
+		let progress_bar = if opt.hide_progress {
+			ProgressBar::hidden()
+		}
+		else {
+			ProgressBar::new_spinner()
+				.with_style(ProgressStyle::default_spinner()
+					.template("  => {pos} input blocks"))
+		};
+
+		// Only one chunk of blocks and its pairs are kept in memory
+		let mut reader = block_reader(&opt, &sequences)?;
+		loop {
+			let blocks = reader.by_ref()
+				.take(opt.chunk_size)
+				.collect::<Result<Vec<PBlock>, GapsError>>()?;
+			if blocks.is_empty() {
+				break;
+			}
+
+			progress_bar.inc(blocks.len() as u64);
+			let pairs = find_pairs(blocks, &sequences, &patterns, &opt, ProgressBar::hidden())?;
+			let pairs = resolve_conflicts(pairs, &opt, &mut stats);
+			write_pairs(&mut sinks, &mut stats, &pairs)?;
+		}
+		progress_bar.finish();
+
+		if !opt.hide_progress { println!("  (Finished in {}s)", sw.elapsed_ms() as f32/1000.0); }
+
+		report_input_blocks(&reader, &opt, &mut stats);
+	}
+	else {
+		if !opt.hide_progress { print!("- Reading PBlock file"); }
 
-	if !opt.hide_progress { println!("\t\t(Finished in {}s)\n  => {} input blocks", sw.elapsed_ms() as f32/1000.0, blocks.len()); }
+		flush_stdout()?;
+		sw.restart();
+		let mut reader = block_reader(&opt, &sequences)?;
+		let blocks = reader.by_ref().collect::<Result<Vec<PBlock>, GapsError>>()?;
 
-	// =============================================================================================
+		if !opt.hide_progress { println!("\t\t(Finished in {}s)\n  => {} input blocks", sw.elapsed_ms() as f32/1000.0, blocks.len()); }
 
-	stdout().flush().unwrap();
-	sw.restart();
+		report_input_blocks(&reader, &opt, &mut stats);
 
-	let progress_bar = if opt.hide_progress {
-		ProgressBar::hidden()
-	}
-	else {
-		ProgressBar::new(blocks.len() as u64)
-			.with_style(ProgressStyle::default_bar()
-				.template("- Searching for pairs\t\t{bar:20}"))
-	};
+		// =========================================================================================
 
-	let mut pairs = blocks.into_par_iter()
-		.progress_with(progress_bar)
-		.filter_map(|block| {
-			if let Some(block2) = PBlock::find_matching_block(&block, &sequences, &opt.pattern, opt.range) {
-				Some((block, block2))
-			} else { None }
-		})
-		.collect::<Vec<(PBlock, PBlock)>>();
+		flush_stdout()?;
+		sw.restart();
 
-	// Filter pairs
-	pairs = if !opt.all && !opt.weak {
-		pairs.into_iter().filter(|a| PBlock::strong_pair(&a.0, &a.1)).collect()
-	}
-	else {
-		pairs.into_iter().filter(|a| QTree::new(&a.0, &a.1).is_some() && (!PBlock::strong_pair(&a.0, &a.1) || opt.all)).collect()
-	};
+		let progress_bar = if opt.hide_progress {
+			ProgressBar::hidden()
+		}
+		else {
+			ProgressBar::new(blocks.len() as u64)
+				.with_style(ProgressStyle::default_bar()
+					.template("- Searching for pairs\t\t{bar:20}"))
+		};
 
-	if !opt.hide_progress { println!("\r- Searching for pairs\t\t(Finished in {}s)", sw.elapsed_ms() as f32/1000.0); }
+		let pairs = find_pairs(blocks, &sequences, &patterns, &opt, progress_bar)?;
+		let pairs = resolve_conflicts(pairs, &opt, &mut stats);
+		write_pairs(&mut sinks, &mut stats, &pairs)?;
+
+		if !opt.hide_progress { println!("\r- Searching for pairs\t\t(Finished in {}s)", sw.elapsed_ms() as f32/1000.0); }
+	}
 
 	// =============================================================================================
 
 	if !opt.hide_progress { print!("- Saving result to file"); }
 
-	stdout().flush().unwrap();
+	flush_stdout()?;
 	sw.restart();
-	match &opt.format[..] {
-		"max-cut" => output::to_nwk(&QTree::from_pairs(&pairs), &opt.outfile),
-		"phylip" => output::to_phylip_pars(&pairs, &opt.outfile),
-		"paup" => output::to_paup(&pairs, &opt.outfile),
-		_ => panic!("Invalid format (should have been caught by structopt)")
+	let mut truncated = 0;
+	for sink in &mut sinks {
+		truncated += sink.finish()?;
 	}
 
-	if opt.print_pairs {
-		output::pairs_to_file(&pairs, "pairs.txt");
+	if truncated > 0 {
+		eprintln!("\nWarning: {} of {} characters have more states than the output format can represent; the additional states were replaced by missing information", truncated, stats.total_pairs);
 	}
 
 	if !opt.hide_progress { println!("\t\t(Finished in {}s)", sw.elapsed_ms() as f32/1000.0); }
@@ -83,7 +144,159 @@ pub fn run(opt: crate::opt::Gaps) -> Result<Stats, String> {
 
 	if !opt.hide_progress { println!("\t\t\t\t(Total time: {}s)\n", time_all.elapsed_ms() as f32/1000.0); }
 
-    Ok(Stats::new(&pairs, sequences.len()))
+	stats.truncated_columns = truncated;
+
+	Ok(stats)
+}
+
+// Collects the patterns for new blocks from the pattern file and random generation, or from the
+// pattern list if neither is used (duplicates are removed)
+fn patterns(opt: &crate::opt::Gaps) -> Result<Vec<Pattern>, GapsError> {
+	let mut patterns = Vec::new();
+	if let Some(filename) = &opt.pattern_file {
+		patterns.extend(pattern::read_file(filename)?);
+	}
+	if opt.random_patterns > 0 {
+		patterns.extend(pattern::random(opt.random_patterns, opt.pattern_weight, opt.pattern_length, opt.pattern_seed)?);
+	}
+	if opt.pattern_file.is_none() && opt.random_patterns == 0 {
+		patterns = pattern::parse_list(&opt.pattern).map_err(|e| GapsError::Invalid(format!("Invalid pattern {}", e)))?;
+	}
+
+	let mut result: Vec<Pattern> = Vec::with_capacity(patterns.len());
+	for p in patterns {
+		if !result.contains(&p) {
+			result.push(p);
+		}
+	}
+
+	if result.is_empty() {
+		return Err(GapsError::Invalid(String::from("No pattern for new blocks given")));
+	}
+
+	// Spaced words have to fit into the search window
+	let window = search_window(opt);
+	if let Some(p) = result.iter().find(|p| p.len() as i64 >= window.upstream + window.downstream) {
+		return Err(GapsError::Invalid(format!("Pattern {} doesn't fit into the search window of {} bases", p, window.upstream + window.downstream)));
+	}
+
+	Ok(result)
+}
+
+fn search_window(opt: &crate::opt::Gaps) -> SearchWindow {
+	match &opt.direction[..] {
+		"upstream" => SearchWindow { upstream: opt.range_upstream, downstream: 0 },
+		"both" => SearchWindow { upstream: opt.range_upstream, downstream: opt.range },
+		_ => SearchWindow { upstream: 0, downstream: opt.range }
+	}
+}
+
+// Opens the input blocks, with verification and scoring of their words if requested
+fn block_reader<'a>(opt: &crate::opt::Gaps, sequences: &'a HashMap<String, Sequence>) -> Result<BlockReader<'a>, GapsError> {
+	let mut reader = PBlock::reader(&opt.infile, sequences)?;
+	if opt.verify || opt.verify_pattern.is_some() || opt.drop_mismatches {
+		reader = reader.verify(opt.verify_pattern.clone(), opt.drop_mismatches);
+	}
+	if let Some(min_score) = opt.min_anchor_score {
+		reader = reader.min_score(min_score, opt.anchor_pattern.clone());
+	}
+	Ok(reader)
+}
+
+// Reports the input blocks that were dropped or don't match the sequences
+fn report_input_blocks(reader: &BlockReader, opt: &crate::opt::Gaps, stats: &mut Stats) {
+	stats.low_score_blocks = reader.low_scores();
+	if reader.low_scores() > 0 && !opt.hide_progress {
+		println!("  => {} input blocks dropped because of their score", reader.low_scores());
+	}
+
+	stats.mismatched_blocks = reader.mismatches();
+	if reader.mismatches() == 0 {
+		return;
+	}
+
+	eprintln!("\nWarning: the spaced words of {} input blocks don't match the sequences{}", reader.mismatches(),
+		if opt.drop_mismatches { "; these blocks were dropped" } else { "" });
+	for mismatch in reader.reported_mismatches() {
+		eprintln!("  {}", mismatch);
+	}
+	if reader.mismatches() > reader.reported_mismatches().len() {
+		eprintln!("  ...");
+	}
+}
+
+// Searches a matching block for each input block (in parallel) and keeps the pairs that are
+// selected by the options
+fn find_pairs(blocks: Vec<PBlock>, sequences: &HashMap<String, Sequence>, patterns: &[Pattern], opt: &crate::opt::Gaps, progress_bar: ProgressBar) -> Result<Vec<(PBlock, PBlock)>, GapsError> {
+	let policy = match &opt.match_policy[..] {
+		"closest" => MatchPolicy::Closest,
+		"all" => MatchPolicy::All,
+		_ => MatchPolicy::First
+	};
+
+	let window = search_window(opt);
+	let tolerance = Tolerance {
+		mismatches: opt.mismatches,
+		min_score: opt.min_score,
+		min_dont_care_score: opt.min_dont_care_score
+	};
+
+	let candidates = blocks.into_par_iter()
+		.progress_with(progress_bar)
+		.map(|block| {
+			let matches = match &opt.matcher[..] {
+				"hash" => PBlock::find_matching_blocks(&block, sequences, patterns, window, tolerance, policy, opt.pairs_per_block)?,
+				_ => PBlock::find_matching_block(&block, sequences, patterns, window, tolerance)?.into_iter().collect()
+			};
+			Ok(matches.into_iter()
+				.map(|block2| (block.clone(), block2))
+				.collect::<Vec<_>>())
+		})
+		.collect::<Result<Vec<Vec<(PBlock, PBlock)>>, GapsError>>()?;
+
+	// The quartet formats apply the indel range to each quartet of a pair instead (see sink::create)
+	let indels = if opt.format == "max-cut" {
+		IndelRange::ANY
+	}
+	else {
+		IndelRange { min: opt.min_indel, max: opt.max_indel }
+	};
+	let pairs = candidates.into_iter()
+		.flatten()
+		.filter(|(a, b)| indels.accepts(a, b));
+
+	// Filter pairs
+	Ok(if !opt.all && !opt.weak {
+		pairs.filter(|a| PBlock::strong_pair(&a.0, &a.1)).collect()
+	}
+	else {
+		pairs.filter(|a| PBlock::informative_pair(&a.0, &a.1) && (!PBlock::strong_pair(&a.0, &a.1) || opt.all)).collect()
+	})
+}
+
+// Applies the locus policy to pairs with conflicting topologies at the same locus
+fn resolve_conflicts(pairs: Vec<(PBlock, PBlock)>, opt: &crate::opt::Gaps, stats: &mut Stats) -> Vec<(PBlock, PBlock)> {
+	let policy = match &opt.locus_policy[..] {
+		"drop" => LocusPolicy::Drop,
+		"majority" => LocusPolicy::Majority,
+		_ => LocusPolicy::Keep
+	};
+
+	let (pairs, conflicts) = locus::resolve_conflicts(pairs, policy);
+	stats.conflicting_loci += conflicts;
+	pairs
+}
+
+fn write_pairs(sinks: &mut [Box<dyn PairSink>], stats: &mut Stats, pairs: &[(PBlock, PBlock)]) -> Result<(), GapsError> {
+	for sink in sinks {
+		sink.write_pairs(pairs)?;
+	}
+	stats.add_pairs(pairs);
+	Ok(())
+}
+
+fn flush_stdout() -> Result<(), GapsError> {
+	stdout().flush().map_err(|e| GapsError::io("stdout", e))
 }
 
 // =================================================================================================
@@ -94,8 +307,20 @@ pub mod opt;
 
 pub mod tools;
 
+pub mod pattern;
+pub use self::pattern::Pattern;
+
 pub mod output;
 
+pub mod sink;
+
+pub mod locus;
+
+pub mod newick;
+
+mod error;
+pub use self::error::GapsError;
+
 
 mod stats;
 pub use self::stats::Stats;
@@ -103,6 +328,9 @@ pub use self::stats::Stats;
 mod qtree;
 pub use self::qtree::QTree;
 
+mod quartet;
+pub use self::quartet::{Quartet, Weighting};
+
 mod sequence;
 pub use self::sequence::Sequence;
 
@@ -110,4 +338,87 @@ mod spaced_word;
 pub use self::spaced_word::SpacedWord;
 
 mod pblock;
-pub use self::pblock::PBlock;
\ No newline at end of file
+pub use self::pblock::{PBlock, BlockReader, Candidate, MatchPolicy, SearchWindow, Tolerance, IndelRange};
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+	use std::fs;
+	use structopt::StructOpt;
+
+	const SITES: usize = 10;
+	const NAMES: [&str; 5] = ["A", "B", "C", "D", "E"];
+
+	// Sequences with an insertion of three bases at every site in two of the first four sequences,
+	// and blocks upstream of each site. E only occurs in the blocks of the second half, so its
+	// characters start in a later chunk.
+	fn write_input(dir: &std::path::Path) -> (String, String) {
+		let mut rng = StdRng::seed_from_u64(3);
+		let base = (0..SITES * 200 + 500).map(|_| ['A', 'C', 'G', 'T'][rng.gen_range(0..4)]).collect::<String>();
+		let inserted = |name: usize, site: usize| name == 0 || name == 1 + site % 3;
+
+		let mut fasta = String::new();
+		for (i, name) in NAMES.iter().enumerate() {
+			let mut seq = base.clone();
+			for site in (0..SITES).rev() {
+				if inserted(i, site) {
+					seq.insert_str(site * 200 + 120, "TTG");
+				}
+			}
+			fasta += &format!(">{}\n{}\n", name, seq);
+		}
+
+		let mut blocks = String::new();
+		for site in 0..SITES {
+			let names = if site < SITES / 2 { 4 } else { 5 };
+			for (i, name) in NAMES[..names].iter().enumerate() {
+				let shift = (0..site).filter(|s| inserted(i, *s)).count() * 3;
+				blocks += &format!(">{} (Pos: {} RevComp: 0)\n", name, site * 200 + 50 + shift);
+			}
+			blocks += "\n";
+		}
+
+		let fastafile = dir.join("seqs.fa").to_string_lossy().into_owned();
+		let infile = dir.join("blocks.txt").to_string_lossy().into_owned();
+		fs::write(&fastafile, fasta).unwrap();
+		fs::write(&infile, blocks).unwrap();
+		(infile, fastafile)
+	}
+
+	#[test]
+	fn streaming_gives_the_same_output() {
+		let dir = std::env::temp_dir().join(format!("gaps_rs_test_stream_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let (infile, fastafile) = write_input(&dir);
+
+		let formats: [&[&str]; 5] = [
+			&["--format", "paup"],
+			&["--format", "paup", "--coding", "stepmatrix"],
+			&["--format", "phylip"],
+			&["--format", "splits"],
+			&["--format", "max-cut", "--weights", "count"]
+		];
+		for (i, format) in formats.iter().enumerate() {
+			let output = |stream: &[&str]| {
+				let outfile = dir.join(format!("out_{}_{}", i, stream.len())).to_string_lossy().into_owned();
+				let mut args = vec!["gaps", "-i", &infile, "-f", &fastafile, "-o", &outfile, "--all", "--hide-progress"];
+				args.extend_from_slice(format);
+				args.extend_from_slice(stream);
+				let stats = run(crate::opt::Gaps::from_iter(args)).unwrap();
+				let taxa = fs::read_to_string(format!("{}.taxa", outfile)).ok();
+				(fs::read_to_string(&outfile).unwrap(), taxa, stats.total_pairs)
+			};
+
+			let expected = output(&[]);
+			assert!(expected.2 >= SITES / 2, "{:?}", format);
+			assert_eq!(output(&["--stream", "--chunk-size", "3"]), expected, "{:?}", format);
+		}
+
+		// E has missing information in the columns before it occurs for the first time
+		let paup = fs::read_to_string(dir.join("out_0_3")).unwrap();
+		assert!(paup.contains(&format!("\n'E'{}", " ?".repeat(SITES / 2))));
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
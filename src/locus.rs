@@ -0,0 +1,166 @@
+use crate::PBlock;
+use std::collections::HashMap;
+
+// How pairs at the same locus with conflicting topologies are handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LocusPolicy {
+	// all pairs are kept
+	Keep,
+	// all pairs of the locus are dropped
+	Drop,
+	// only the pairs with the most frequent topology are kept (none, if there is a tie)
+	Majority,
+}
+
+/// Groups the pairs into loci: two pairs belong to the same locus, if they have the same sequences
+/// and the regions covered by their blocks overlap in every sequence (transitively). Returns the
+/// indices of the pairs of each locus.
+pub fn clusters(pairs: &[(PBlock, PBlock)]) -> Vec<Vec<usize>> {
+	let regions = pairs.iter().map(regions).collect::<Vec<_>>();
+
+	let mut groups: HashMap<Vec<&str>, Vec<usize>> = HashMap::new();
+	for (i, (block, _)) in pairs.iter().enumerate() {
+		groups.entry(block.get_sequence_names()).or_default().push(i);
+	}
+
+	let mut parent = (0..pairs.len()).collect::<Vec<_>>();
+	for mut group in groups.into_values() {
+		// Sweep over the regions of the first sequence; only pairs whose region hasn't ended yet
+		// can overlap the current one
+		group.sort_unstable_by_key(|&i| regions[i][0]);
+		let mut active: Vec<usize> = Vec::new();
+		for i in group {
+			active.retain(|&j| regions[j][0].1 > regions[i][0].0);
+			for &j in &active {
+				if regions[i].iter().zip(&regions[j]).all(|(a, b)| a.0 < b.1 && b.0 < a.1) {
+					let (root_i, root_j) = (find(&mut parent, i), find(&mut parent, j));
+					parent[root_i] = root_j;
+				}
+			}
+			active.push(i);
+		}
+	}
+
+	let mut loci: HashMap<usize, Vec<usize>> = HashMap::new();
+	for i in 0..pairs.len() {
+		loci.entry(find(&mut parent, i)).or_default().push(i);
+	}
+
+	let mut result = loci.into_values().collect::<Vec<_>>();
+	result.sort_unstable();
+	result
+}
+
+/// Applies the policy to the loci (see clusters) with conflicting topologies, i.e. whose pairs imply
+/// different splits. Returns the remaining pairs in their original order and the number of loci
+/// with conflicts.
+pub fn resolve_conflicts(pairs: Vec<(PBlock, PBlock)>, policy: LocusPolicy) -> (Vec<(PBlock, PBlock)>, usize) {
+	let mut keep = vec![true; pairs.len()];
+	let mut conflicts = 0;
+
+	for locus in clusters(&pairs) {
+		let topologies = locus.iter()
+			.map(|&i| topology(&pairs[i]))
+			.collect::<Vec<_>>();
+		if topologies.iter().all(|t| *t == topologies[0]) {
+			continue;
+		}
+		conflicts += 1;
+
+		let mut counts: HashMap<&Vec<Vec<&str>>, usize> = HashMap::new();
+		for t in &topologies {
+			*counts.entry(t).or_default() += 1;
+		}
+		let max = *counts.values().max().unwrap();
+		let majority = counts.iter()
+			.filter(|(_, count)| **count == max)
+			.map(|(t, _)| *t)
+			.collect::<Vec<_>>();
+
+		for (&i, t) in locus.iter().zip(&topologies) {
+			keep[i] = match policy {
+				LocusPolicy::Keep => true,
+				LocusPolicy::Drop => false,
+				LocusPolicy::Majority => majority.len() == 1 && majority[0] == t
+			};
+		}
+	}
+
+	let pairs = pairs.into_iter()
+		.zip(keep)
+		.filter(|(_, keep)| *keep)
+		.map(|(pair, _)| pair)
+		.collect();
+	(pairs, conflicts)
+}
+
+// Forward strand region covered by both words of each sequence
+fn regions((a, b): &(PBlock, PBlock)) -> Vec<(i64, i64)> {
+	a.words.iter().zip(&b.words)
+		.map(|(x, y)| {
+			let end = |word: &crate::SpacedWord| word.position + word.len.max(1) as i64;
+			(x.position.min(y.position), end(x).max(end(y)))
+		})
+		.collect()
+}
+
+// The splits of a pair (see PBlock::splits), each given by the side without the first sequence
+fn topology((a, b): &(PBlock, PBlock)) -> Vec<Vec<&str>> {
+	let first = &*a[0].seq_name;
+	let mut result = PBlock::splits(a, b).into_iter()
+		.map(|(x, y)| if x.contains(&first) { y } else { x })
+		.map(|mut side| { side.sort_unstable(); side })
+		.collect::<Vec<_>>();
+	result.sort_unstable();
+	result.dedup();
+	result
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+	let mut root = i;
+	while parent[root] != root {
+		root = parent[root];
+	}
+	// Path compression
+	let mut i = i;
+	while parent[i] != root {
+		let next = parent[i];
+		parent[i] = root;
+		i = next;
+	}
+	root
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pair(start: i64, gaps: [i64; 4]) -> (PBlock, PBlock) {
+		PBlock::pair_with_gaps(&["A", "B", "C", "D"], start, &gaps)
+	}
+
+	#[test]
+	fn conflicting_loci_are_resolved() {
+		let pairs = vec![
+			pair(0, [20, 20, 23, 23]),
+			pair(5, [20, 20, 23, 23]),
+			pair(10, [20, 23, 20, 23]),
+			// Separate locus
+			pair(1000, [20, 23, 20, 23])
+		];
+		assert_eq!(clusters(&pairs), vec![vec![0, 1, 2], vec![3]]);
+
+		let (kept, conflicts) = resolve_conflicts(pairs.clone(), LocusPolicy::Keep);
+		assert_eq!((kept.len(), conflicts), (4, 1));
+
+		let (kept, _) = resolve_conflicts(pairs.clone(), LocusPolicy::Drop);
+		assert_eq!(kept.iter().map(|(a, _)| a[0].position).collect::<Vec<_>>(), vec![1000]);
+
+		let (kept, _) = resolve_conflicts(pairs.clone(), LocusPolicy::Majority);
+		assert_eq!(kept.iter().map(|(a, _)| a[0].position).collect::<Vec<_>>(), vec![0, 5, 1000]);
+
+		// No majority without the second pair
+		let (kept, _) = resolve_conflicts(vec![pairs[0].clone(), pairs[2].clone()], LocusPolicy::Majority);
+		assert!(kept.is_empty());
+	}
+}
@@ -0,0 +1,317 @@
+use crate::GapsError;
+use std::collections::HashSet;
+
+// === Newick trees ============================================================
+
+#[derive(Debug, Clone)]
+pub struct Tree {
+	// nodes[0] is the root
+	nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+	label: Option<String>,
+	children: Vec<usize>,
+}
+
+// A split is stored as the sorted indices (into the sorted leaf list) of the side that
+// does not contain the first leaf
+type Split = Vec<usize>;
+
+impl Tree {
+	/// Parses a single tree in Newick notation (the trailing ';' is optional)
+	pub fn parse(input: &str) -> Result<Tree, GapsError> {
+		let mut parser = Parser { chars: input.chars().collect(), pos: 0, nodes: Vec::new() };
+		parser.nodes.push(Node::default());
+		parser.parse_tree().map_err(|msg| GapsError::Invalid(format!("Invalid Newick tree: {}", msg)))?;
+
+		Ok(Tree { nodes: parser.nodes })
+	}
+
+	/// Parses all trees of a string, each of them terminated by ';'
+	pub fn parse_all(input: &str) -> Result<Vec<Tree>, GapsError> {
+		let mut trees = Vec::new();
+		let mut current = String::new();
+		let mut quoted = false;
+
+		for c in input.chars() {
+			current.push(c);
+			match c {
+				'\'' => quoted = !quoted,
+				';' if !quoted => {
+					trees.push(Tree::parse(&current)?);
+					current.clear();
+				},
+				_ => {}
+			}
+		}
+
+		if !current.trim().is_empty() {
+			trees.push(Tree::parse(&current)?);
+		}
+
+		Ok(trees)
+	}
+
+	/// Returns the sorted labels of all leaves
+	pub fn leaves(&self) -> Vec<&str> {
+		let mut result = self.nodes.iter()
+			.filter(|node| node.children.is_empty())
+			.filter_map(|node| node.label.as_deref())
+			.collect::<Vec<_>>();
+		result.sort_unstable();
+		result
+	}
+
+	// Returns all non-trivial splits of the (unrooted) tree
+	fn splits(&self, leaves: &[&str]) -> HashSet<Split> {
+		let mut below = vec![Vec::new(); self.nodes.len()];
+
+		// Children always have a larger index than their parent
+		for i in (0..self.nodes.len()).rev() {
+			let node = &self.nodes[i];
+			if node.children.is_empty() {
+				if let Some(label) = &node.label {
+					if let Ok(index) = leaves.binary_search(&&label[..]) {
+						below[i].push(index);
+					}
+				}
+			}
+			else {
+				let mut set = Vec::new();
+				for child in &node.children {
+					set.extend_from_slice(&below[*child]);
+				}
+				set.sort_unstable();
+				below[i] = set;
+			}
+		}
+
+		let mut result = HashSet::new();
+		for set in below.into_iter().skip(1) {
+			if set.len() < 2 || set.len() + 2 > leaves.len() {
+				continue;
+			}
+
+			let split = if set[0] == 0 {
+				(0..leaves.len()).filter(|i| set.binary_search(i).is_err()).collect()
+			}
+			else {
+				set
+			};
+			result.insert(split);
+		}
+		result
+	}
+}
+
+// === Robinson-Foulds distance ================================================
+
+/// Returns the Robinson-Foulds distance (symmetric difference of the splits) between two
+/// unrooted trees with the same set of leaves
+pub fn rf_distance(a: &Tree, b: &Tree) -> Result<usize, GapsError> {
+	let leaves = a.leaves();
+	if leaves != b.leaves() {
+		return Err(GapsError::Invalid(String::from("Trees do not have the same set of leaves")));
+	}
+
+	let splits_a = a.splits(&leaves);
+	let splits_b = b.splits(&leaves);
+
+	Ok(splits_a.symmetric_difference(&splits_b).count())
+}
+
+/// Returns the Robinson-Foulds distance divided by its maximum 2(n-3) for binary trees
+pub fn rf_distance_normalized(a: &Tree, b: &Tree) -> Result<f64, GapsError> {
+	let rf = rf_distance(a, b)?;
+	let n = a.leaves().len();
+
+	if n < 4 {
+		return Ok(0.0);
+	}
+
+	Ok(rf as f64 / (2 * (n - 3)) as f64)
+}
+
+// === Parser ==================================================================
+
+struct Parser {
+	chars: Vec<char>,
+	pos: usize,
+	nodes: Vec<Node>,
+}
+
+impl Parser {
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.pos).copied()
+	}
+
+	fn parse_tree(&mut self) -> Result<(), String> {
+		self.parse_subtree(0)?;
+		self.skip_whitespace();
+
+		if self.peek() == Some(';') {
+			self.pos += 1;
+		}
+		self.skip_whitespace();
+		if self.pos < self.chars.len() {
+			return Err(format!("Unexpected '{}' after end of tree", self.chars[self.pos]));
+		}
+
+		Ok(())
+	}
+
+	fn skip_whitespace(&mut self) {
+		while let Some(c) = self.peek() {
+			if c.is_whitespace() {
+				self.pos += 1;
+			}
+			else if c == '[' {
+				// Comments
+				while let Some(c) = self.peek() {
+					self.pos += 1;
+					if c == ']' {
+						break;
+					}
+				}
+			}
+			else {
+				break;
+			}
+		}
+	}
+
+	fn parse_subtree(&mut self, node: usize) -> Result<(), String> {
+		self.skip_whitespace();
+
+		if self.peek() == Some('(') {
+			loop {
+				self.pos += 1;
+				let child = self.nodes.len();
+				self.nodes.push(Node::default());
+				self.nodes[node].children.push(child);
+				self.parse_subtree(child)?;
+
+				self.skip_whitespace();
+				match self.peek() {
+					Some(',') => continue,
+					Some(')') => { self.pos += 1; break; },
+					Some(c) => return Err(format!("Unexpected '{}' at position {}", c, self.pos)),
+					None => return Err(String::from("Unexpected end of tree")),
+				}
+			}
+		}
+
+		self.skip_whitespace();
+		let label = self.parse_label()?;
+		if label.is_empty() && self.nodes[node].children.is_empty() {
+			return Err(format!("Missing leaf label at position {}", self.pos));
+		}
+		if !label.is_empty() {
+			self.nodes[node].label = Some(label);
+		}
+
+		// Branch length (ignored)
+		self.skip_whitespace();
+		if self.peek() == Some(':') {
+			self.pos += 1;
+			self.skip_whitespace();
+			while let Some(c) = self.peek() {
+				if c.is_ascii_digit() || "+-.eE".contains(c) {
+					self.pos += 1;
+				}
+				else {
+					break;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn parse_label(&mut self) -> Result<String, String> {
+		let mut label = String::new();
+
+		if self.peek() == Some('\'') {
+			self.pos += 1;
+			loop {
+				match self.peek() {
+					Some('\'') if self.chars.get(self.pos + 1) == Some(&'\'') => {
+						label.push('\'');
+						self.pos += 2;
+					},
+					Some('\'') => { self.pos += 1; break; },
+					Some(c) => { label.push(c); self.pos += 1; },
+					None => return Err(String::from("Unterminated quoted label")),
+				}
+			}
+			return Ok(label);
+		}
+
+		while let Some(c) = self.peek() {
+			if c.is_whitespace() || "(),:;[".contains(c) {
+				break;
+			}
+			label.push(c);
+			self.pos += 1;
+		}
+
+		Ok(label)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rf(a: &str, b: &str) -> usize {
+		rf_distance(&Tree::parse(a).unwrap(), &Tree::parse(b).unwrap()).unwrap()
+	}
+
+	#[test]
+	fn rooted_and_unrooted_trees_have_the_same_splits() {
+		let rooted = Tree::parse("((a,b),(c,(d,e)));").unwrap();
+		let unrooted = Tree::parse("(a,b,(c,(d,e)));").unwrap();
+		let leaves = rooted.leaves();
+		assert_eq!(rooted.splits(&leaves), unrooted.splits(&leaves));
+		assert_eq!(rooted.splits(&leaves).len(), 2);
+		assert_eq!(rf_distance(&rooted, &unrooted).unwrap(), 0);
+	}
+
+	#[test]
+	fn quoted_labels_and_comments_are_parsed() {
+		let tree = Tree::parse("('a b',[comment (x,y)]'c''d',(e[&&NHX:S=1],f)) [root];").unwrap();
+		assert_eq!(tree.leaves(), vec!["a b", "c'd", "e", "f"]);
+
+		let trees = Tree::parse_all("(a,b,('c;d',e));\n(a,e,(b,'c;d'));").unwrap();
+		assert_eq!(trees.len(), 2);
+		assert_eq!(rf_distance(&trees[0], &trees[1]).unwrap(), 2);
+
+		assert!(Tree::parse("(a,'b,c);").is_err());
+		assert!(Tree::parse("(a,,b);").is_err());
+	}
+
+	#[test]
+	fn branch_lengths_are_ignored() {
+		assert_eq!(rf("((a:0.1,b:1e-3):2,(c : 1,d:+1.5E2)inner:0.5);", "((a,b),(c,d));"), 0);
+		assert_eq!(Tree::parse("(a:1,b:2,(c:3,d:4):5);").unwrap().leaves(), vec!["a", "b", "c", "d"]);
+	}
+
+	#[test]
+	fn leaf_sets_have_to_match() {
+		let a = Tree::parse("((a,b),(c,d));").unwrap();
+		let b = Tree::parse("((a,b),(c,e));").unwrap();
+		assert!(matches!(rf_distance(&a, &b), Err(GapsError::Invalid(_))));
+		assert!(rf_distance_normalized(&a, &b).is_err());
+	}
+
+	#[test]
+	fn known_distance() {
+		let a = Tree::parse("((a,b),(c,d),e);").unwrap();
+		let b = Tree::parse("(a,(b,(c,(d,e))));").unwrap();
+		assert_eq!(rf_distance(&a, &b).unwrap(), 2);
+		assert_eq!(rf_distance_normalized(&a, &b).unwrap(), 0.5);
+		assert_eq!(rf_distance(&a, &a).unwrap(), 0);
+	}
+}
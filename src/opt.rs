@@ -1,4 +1,5 @@
 use structopt::StructOpt;
+use crate::Pattern;
 
 // === Options for gaps-binary =================================================
 
@@ -9,6 +10,62 @@ fn check_format(input: &str) -> Result<String, String> {
 	}
 }
 
+fn check_output_format(input: &str) -> Result<String, String> {
+	match input {
+		"max-cut" | "paup" | "phylip" | "splits" => Ok(input.to_string()),
+		_ => Err(input.to_string())
+	}
+}
+
+fn check_weights(input: &str) -> Result<String, String> {
+	match input {
+		"off" | "count" | "class" | "gap" => Ok(input.to_string()),
+		_ => Err(input.to_string())
+	}
+}
+
+fn check_coding(input: &str) -> Result<String, String> {
+	match input {
+		"unordered" | "ordered" | "stepmatrix" => Ok(input.to_string()),
+		_ => Err(input.to_string())
+	}
+}
+
+fn check_direction(input: &str) -> Result<String, String> {
+	match input {
+		"downstream" | "upstream" | "both" => Ok(input.to_string()),
+		_ => Err(input.to_string())
+	}
+}
+
+fn check_matcher(input: &str) -> Result<String, String> {
+	match input {
+		"sorted" | "hash" => Ok(input.to_string()),
+		_ => Err(input.to_string())
+	}
+}
+
+fn check_match_policy(input: &str) -> Result<String, String> {
+	match input {
+		"first" | "closest" | "all" => Ok(input.to_string()),
+		_ => Err(input.to_string())
+	}
+}
+
+fn check_locus_policy(input: &str) -> Result<String, String> {
+	match input {
+		"keep" | "drop" | "majority" => Ok(input.to_string()),
+		_ => Err(input.to_string())
+	}
+}
+
+fn check_chunk_size(input: &str) -> Result<usize, String> {
+	match input.parse::<usize>() {
+		Ok(size) if size > 0 => Ok(size),
+		_ => Err(format!("{} (expected a positive number)", input))
+	}
+}
+
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(name = "gaps", about = "Mind the gap!")]
 pub struct Gaps {
@@ -22,16 +79,103 @@ pub struct Gaps {
 	#[structopt(short = "o", default_value = "outfile")]
 	pub outfile: String,
 
-	/// Output format (max-cut|paup)
-	#[structopt(long = "format", default_value = "paup", parse(try_from_str = check_format))]
+	/// Output format (max-cut|paup|phylip|splits)
+	#[structopt(long = "format", default_value = "paup", parse(try_from_str = check_output_format))]
 	pub format: String,
+	/// Quartet weights for max-cut output (off|count|class|gap)
+	#[structopt(long = "weights", default_value = "off", parse(try_from_str = check_weights))]
+	pub weights: String,
+	/// Character coding for paup output (unordered|ordered|stepmatrix)
+	#[structopt(long = "coding", default_value = "unordered", parse(try_from_str = check_coding))]
+	pub coding: String,
 
-	/// pattern for new blocks
+	/// pattern(s) for new blocks (comma-separated; 1 for match, 0 or * for don't care positions)
 	#[structopt(short = "p", long = "pattern", default_value = "1111111")]
 	pub pattern: String,
+	/// File with one pattern per line (replaces --pattern)
+	#[structopt(long = "pattern-file")]
+	pub pattern_file: Option<String>,
+	/// Number of random patterns (replaces --pattern)
+	#[structopt(long = "random-patterns", default_value = "0")]
+	pub random_patterns: usize,
+	/// Number of match positions of the random patterns
+	#[structopt(long = "pattern-weight", default_value = "7")]
+	pub pattern_weight: usize,
+	/// Length of the random patterns
+	#[structopt(long = "pattern-length", default_value = "11")]
+	pub pattern_length: usize,
+	/// Seed for the random patterns
+	#[structopt(long = "pattern-seed", default_value = "1")]
+	pub pattern_seed: u64,
 	/// range for new blocks
 	#[structopt(long = "range", default_value = "500")]
 	pub range: i64,
+	/// range for new blocks upstream of the input blocks
+	#[structopt(long = "range-upstream", default_value = "500")]
+	pub range_upstream: i64,
+	/// Search direction relative to the input blocks (downstream|upstream|both)
+	#[structopt(long = "direction", default_value = "downstream", parse(try_from_str = check_direction))]
+	pub direction: String,
+	/// Search for new blocks (sorted|hash)
+	#[structopt(long = "matcher", default_value = "sorted", parse(try_from_str = check_matcher))]
+	pub matcher: String,
+	/// Matches that are used with the hash matcher (first|closest|all)
+	#[structopt(long = "match-policy", default_value = "first", parse(try_from_str = check_match_policy))]
+	pub match_policy: String,
+	/// Maximum number of pairs per input block with the hash matcher (0 for no limit)
+	#[structopt(long = "pairs-per-block", default_value = "1")]
+	pub pairs_per_block: usize,
+
+	/// Number of match positions at which the words of new blocks may differ from the word of the first sequence
+	#[structopt(long = "mismatches", default_value = "0")]
+	pub mismatches: usize,
+	/// Minimum score of new blocks (pairs of words score +1 for each equal and -1 for each different match position)
+	#[structopt(long = "min-score", allow_hyphen_values = true)]
+	pub min_score: Option<i64>,
+
+	/// Minimum score of the don't care positions of new blocks (HOXD70 scores summed over all pairs of words)
+	#[structopt(long = "min-dont-care-score", allow_hyphen_values = true)]
+	pub min_dont_care_score: Option<i64>,
+	/// Minimum score of the input blocks (like --min-dont-care-score; requires the spaced words in the input file)
+	#[structopt(long = "min-anchor-score", allow_hyphen_values = true)]
+	pub min_anchor_score: Option<i64>,
+	/// Pattern of the input blocks whose don't care positions are scored (all positions are scored without it)
+	#[structopt(long = "anchor-pattern")]
+	pub anchor_pattern: Option<Pattern>,
+
+	/// Exclude lower case (soft-masked) regions of the sequences from new blocks
+	#[structopt(long = "soft-mask")]
+	pub soft_mask: bool,
+	/// Exclude low-complexity regions of the sequences (found with DUST) from new blocks
+	#[structopt(long = "dust")]
+	pub dust: bool,
+	/// Window length of DUST
+	#[structopt(long = "dust-window", default_value = "64")]
+	pub dust_window: usize,
+	/// Score above which DUST masks a window
+	#[structopt(long = "dust-threshold", default_value = "20")]
+	pub dust_threshold: f64,
+
+	/// Check the spaced words of the input blocks against the sequences
+	#[structopt(long = "verify")]
+	pub verify: bool,
+	/// Only compare the match positions of this pattern when verifying, e.g. the Multi-SpaM pattern (implies --verify)
+	#[structopt(long = "verify-pattern")]
+	pub verify_pattern: Option<Pattern>,
+	/// Drop input blocks whose spaced words don't match the sequences (implies --verify)
+	#[structopt(long = "drop-mismatches")]
+	pub drop_mismatches: bool,
+
+	/// Minimum indel length of pairs (difference between the gap sizes of the groups of a split)
+	#[structopt(long = "min-indel")]
+	pub min_indel: Option<usize>,
+	/// Maximum indel length of pairs
+	#[structopt(long = "max-indel")]
+	pub max_indel: Option<usize>,
+
+	/// Pairs at the same locus with conflicting topologies (keep|drop|majority)
+	#[structopt(long = "locus-policy", default_value = "keep", parse(try_from_str = check_locus_policy))]
+	pub locus_policy: String,
 
 	/// use all pairs, regardless of strong or weak support
 	#[structopt(long = "all")]
@@ -44,6 +188,13 @@ pub struct Gaps {
 	pub hide_progress: bool,
 	#[structopt(long = "print-pairs")]
 	pub print_pairs: bool,
+
+	/// read and process the blocks in chunks instead of loading the whole file
+	#[structopt(long = "stream")]
+	pub stream: bool,
+	/// number of blocks per chunk in streaming mode
+	#[structopt(long = "chunk-size", default_value = "100000", parse(try_from_str = check_chunk_size))]
+	pub chunk_size: usize,
 }
 
 // === Options for nwk-binary ==================================================
@@ -51,7 +202,7 @@ pub struct Gaps {
 #[derive(StructOpt, Debug)]
 #[structopt(name = "nwk", about = "Constructs a tree from a gaps outfile")]
 pub struct Nwk {
-	/// Method for building trees (max-cut|paup); requires "max-cut-tree"/"paup" to be in path
+	/// Method for building trees (max-cut|paup); paup requires "paup" to be in path
 	#[structopt(long = "method", default_value = "paup", parse(try_from_str = check_format))]
 	pub method: String,
 	/// input file
@@ -68,11 +219,17 @@ pub struct Nwk {
 // === Options for rfdist-binary ===============================================
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "rfdist", about = "Returns the Robinson-Foulds-distancs between two trees (requires phylip treedist to be in path)")]
+#[structopt(name = "rfdist", about = "Returns the Robinson-Foulds-distance between two trees")]
 pub struct Rfdist {
-	/// input file (two trees in FASTA format)
+	/// input file (two trees in Newick format)
 	#[structopt()]
-	pub infile: String
+	pub infile: String,
+	/// divide the distance by its maximum 2(n-3)
+	#[structopt(long = "normalized")]
+	pub normalized: bool,
+	/// cross-check the result with phylip treedist (requires "treedist" to be in path)
+	#[structopt(long = "phylip")]
+	pub phylip: bool
 }
 
 // === Options for benchmark-binary =============================================
@@ -97,9 +254,24 @@ pub struct Benchmark {
 	#[structopt(long = "format", default_value = "paup", parse(try_from_str = check_format))]
 	pub format: String,
 
-	/// pattern for new blocks
+	/// pattern(s) for new blocks (comma-separated; 1 for match, 0 or * for don't care positions)
 	#[structopt(short = "p", long = "pattern", default_value = "1111111")]
 	pub pattern: String,
+	/// File with one pattern per line (replaces --pattern)
+	#[structopt(long = "pattern-file")]
+	pub pattern_file: Option<String>,
+	/// Number of random patterns (replaces --pattern)
+	#[structopt(long = "random-patterns", default_value = "0")]
+	pub random_patterns: usize,
+	/// Number of match positions of the random patterns
+	#[structopt(long = "pattern-weight", default_value = "7")]
+	pub pattern_weight: usize,
+	/// Length of the random patterns
+	#[structopt(long = "pattern-length", default_value = "11")]
+	pub pattern_length: usize,
+	/// Seed for the random patterns
+	#[structopt(long = "pattern-seed", default_value = "1")]
+	pub pattern_seed: u64,
 	/// range for new blocks
 	#[structopt(long = "range", default_value = "500")]
 	pub range: i64,
@@ -1,123 +1,93 @@
-use crate::{PBlock, QTree};
-use std::collections::{HashMap, HashSet};
+use crate::{Quartet, GapsError};
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Write;
 
-// Symbols for parsimony matrix (the last symbol in the array is used for missing information)
-const SYMBOLS: [&str; 9] = ["A", "B", "C", "D", "E", "F", "G", "H", "?"];
-
-pub fn to_nwk(qtrees: &[QTree], filename: &str) {
-    let mut f = File::create(filename).expect("Unable to create file");
-    for tree in qtrees {
-        f.write_all(format!("{}\n", tree).as_bytes()).expect("Unable to write data");
-    }
+// Symbols for parsimony matrix (PAUP doesn't distinguish between upper and lower case)
+pub(crate) const SYMBOLS: [&str; 36] = [
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R",
+    "S", "T", "U", "V", "W", "X", "Y", "Z", "0", "1", "2", "3", "4", "5", "6", "7", "8", "9"
+];
+// Symbol for missing information
+pub(crate) const MISSING: &str = "?";
+
+// Maximum number of states per character that can be represented by the output formats
+pub const MAX_STATES_PAUP: usize = 32;
+pub const MAX_STATES_PHYLIP: usize = 8;
+
+// Coding of the characters in the PAUP matrix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coding {
+    // states only show which sequences share a gap size
+    Unordered,
+    // states are ordered by gap size
+    Ordered,
+    // the cost of a state change is the difference of the gap sizes
+    StepMatrix,
 }
 
-pub fn pairs_to_file(pairs: &[(PBlock, PBlock)], filename: &str) {
-    let mut f = File::create(filename).expect("Unable to create file");
-    for pair in pairs {
-        f.write_all(PBlock::pair_to_string(pair).as_bytes()).expect("Unable to write data");
+pub fn to_weighted_quartets(quartets: &[Quartet], filename: &str) -> Result<(), GapsError> {
+    let mut f = File::create(filename).map_err(|e| GapsError::io(filename, e))?;
+    for quartet in quartets {
+        f.write_all(format!("{}\n", quartet).as_bytes()).map_err(|e| GapsError::io(filename, e))?;
     }
+    Ok(())
 }
 
-pub fn to_phylip_pars(pairs: &[(PBlock, PBlock)], filename: &str) {
-    let (species, pairs, lines) = format_matrix(pairs, 9);
-
-    let mut f = File::create(filename).expect("Unable to create file");
-    f.write_all(format!("{} {}\n{}", species, pairs, lines).as_bytes()).expect("Unable to write data");
+// Quotes a taxon name for NEXUS files, so names may contain whitespace and punctuation
+pub(crate) fn quote_label(name: &str) -> String {
+    format!("'{}'", name.replace('\'', "''"))
 }
 
-pub fn to_paup(pairs: &[(PBlock, PBlock)], filename: &str) {
-    let (species, pairs, lines) = format_matrix(pairs, -1);
-    let head = format!("#NEXUS\n\
-                    begin data;\n\
-                    \tdimensions ntax={} nchar={};\n\
-                    \tformat datatype=standard missing={} interleave symbols=\"{}\";\n\
-                    matrix\n", species, pairs, SYMBOLS.last().expect("SYMBOLS array empty"), SYMBOLS[..SYMBOLS.len()-1].join(""));
-    let tail = ";\n\
-                    end;\n\n\
-                    begin paup;\n\
-                    \tset maxtrees=1000;\n\
-                    \tset increase=auto;\n\
-                    \tHSearch addseq=random nreps=20;\n\
-                    \tSaveTrees format=newick file=pars.nwk replace=yes;\n\
-                    \tquit;\n\
-                    end;";
-
-    let mut f = File::create(filename).expect("Unable to create file");
-    f.write_all(format!("{}\n{}\n{}", head, lines, tail).as_bytes()).expect("Unable to write data");
-}
+// Truncates the names to len characters. If two names are identical after truncation, their last
+// characters are replaced by a number to keep them distinguishable.
+pub(crate) fn truncate_labels(names: &[String], len: usize) -> Vec<String> {
+    let truncated = names.iter()
+        .map(|name| name.chars().take(len).collect::<String>())
+        .collect::<Vec<String>>();
 
-fn format_matrix(pairs: &[(PBlock, PBlock)], name_len: i32) -> (usize, usize, String) {
-    // Collect species
-    let mut species = HashSet::new();
-    for pair in pairs {
-        for name in pair.0.get_sequence_names() {
-            species.insert(String::from(name));
+    let mut used = HashSet::new();
+    let mut result = Vec::with_capacity(names.len());
+    for label in &truncated {
+        let mut label = label.clone();
+
+        if truncated.iter().filter(|l| **l == label).count() > 1 {
+            let prefix = label.clone();
+            for i in 1.. {
+                let suffix = i.to_string();
+                let candidate = format!("{}{}", prefix.chars().take(len.saturating_sub(suffix.len())).collect::<String>(), suffix);
+                if !used.contains(&candidate) && !truncated.contains(&candidate) {
+                    label = candidate;
+                    break;
+                }
+            }
         }
-    }
 
-    let name_len = if name_len == -1 {
-        species.iter().map(|name| name.len()).max().unwrap()
+        used.insert(label.clone());
+        result.push(label);
     }
-    else {
-        name_len as usize
-    };
 
-    let species: Vec<String> = species.into_iter().collect();
+    result
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Build lines
-    let mut output: HashMap<String, Vec<String>> = HashMap::new();
-    for name in &species {
-        let name_padded = if name.len() <= name_len {
-            format!("{}{}", name, " ".repeat(name_len-name.len()))
-        }
-        else {
-            name[0..name_len].to_string()
-        };
-        output.insert(name.to_string(), vec![name_padded]);
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
     }
 
-    let symbols = SYMBOLS.iter()
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>();
-
-    for pair in pairs {
-        let mut symbol_index = 0;
-        let distances: HashMap<String, i64> = PBlock::get_distances(&pair.0, &pair.1);
-        let mut dists_replaced: HashMap<String, String> = HashMap::new();
-        for key in pair.0.get_sequence_names() {
-            dists_replaced.insert(String::from(key), String::from("0"));
-        }
-
-        for (seq, dist) in distances.iter() {
-            if dists_replaced[seq] != "0" {
-                continue;
-            }
-            for seq2 in pair.0.get_sequence_names() {
-                if distances[seq2] == *dist {
-                    dists_replaced.insert(seq2.to_string(), symbols[symbol_index].clone());
-                }
-            }
-            if symbol_index < symbols.len()-1 {
-                symbol_index += 1;
-            }
-        }
-
-        // Add symbols to output lines
-        for s in &species {
-            output.get_mut(s).unwrap().push(
-                dists_replaced.get(s)
-                    .unwrap_or_else(|| symbols.last().unwrap())
-                    .to_string()
-            );
-        }
+    #[test]
+    fn quoted_labels_escape_quotes() {
+        assert_eq!(quote_label("Homo sapiens"), "'Homo sapiens'");
+        assert_eq!(quote_label("O'Brien"), "'O''Brien'");
     }
 
-    let lines = output.values()
-        .map(|line| line.join(" "))
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    (species.len(), pairs.len(), lines)
-}
\ No newline at end of file
+    #[test]
+    fn truncated_labels_stay_distinguishable() {
+        // "Homo sap1" is already taken by the third name
+        let names = strings(&["Homo sapiens chr1", "Homo sapiens chr2", "Homo sap1", "Mus musculus"]);
+        let labels = truncate_labels(&names, 9);
+        assert_eq!(labels, strings(&["Homo sap2", "Homo sap3", "Homo sap1", "Mus muscu"]));
+    }
+}
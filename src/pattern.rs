@@ -0,0 +1,164 @@
+use crate::GapsError;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::index;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Maximum number of match positions; the key of a spaced word uses two bits per match position and
+/// two trailing zero bits (see Sequence::spaced_words)
+pub const MAX_WEIGHT: usize = 63;
+
+/// Pattern of a spaced word: '1' marks a match position, '0' or '*' a don't care position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+	// normalized to '1' and '0'
+	text: Arc<str>,
+	weight: usize,
+}
+
+impl Pattern {
+	pub fn len(&self) -> usize {
+		self.text.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Number of match positions
+	pub fn weight(&self) -> usize {
+		self.weight
+	}
+
+	pub fn is_match(&self, i: usize) -> bool {
+		self.text.as_bytes()[i] == b'1'
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.text
+	}
+
+	/// Offset and length of each run of consecutive match positions
+	pub fn runs(&self) -> Vec<(usize, usize)> {
+		let mut runs: Vec<(usize, usize)> = Vec::new();
+		for (j, p) in self.text.bytes().enumerate() {
+			match runs.last_mut() {
+				Some(run) if p == b'1' && run.0 + run.1 == j => run.1 += 1,
+				_ if p == b'1' => runs.push((j, 1)),
+				_ => ()
+			}
+		}
+		runs
+	}
+}
+
+impl FromStr for Pattern {
+	type Err = String;
+
+	fn from_str(input: &str) -> Result<Pattern, String> {
+		if let Some(c) = input.chars().find(|c| !matches!(c, '1' | '0' | '*')) {
+			return Err(format!("Invalid character '{}' (expected 1, 0 or *)", c));
+		}
+
+		let text = input.replace('*', "0");
+		let weight = text.matches('1').count();
+		if weight == 0 {
+			return Err(String::from("Pattern has no match positions"));
+		}
+		if weight > MAX_WEIGHT {
+			return Err(format!("Pattern has {} match positions (at most {} are supported)", weight, MAX_WEIGHT));
+		}
+
+		Ok(Pattern { text: Arc::from(text), weight })
+	}
+}
+
+impl fmt::Display for Pattern {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.text)
+	}
+}
+
+/// Parses a comma-separated list of patterns
+pub fn parse_list(input: &str) -> Result<Vec<Pattern>, String> {
+	input.split(',')
+		.map(str::trim)
+		.filter(|pattern| !pattern.is_empty())
+		.map(|pattern| pattern.parse().map_err(|e| format!("{}: {}", pattern, e)))
+		.collect()
+}
+
+/// Reads one pattern per line; blank lines and lines starting with '#' are ignored
+pub fn read_file(filename: &str) -> Result<Vec<Pattern>, GapsError> {
+	let content = fs::read_to_string(filename).map_err(|e| GapsError::io(filename, e))?;
+	content.lines()
+		.enumerate()
+		.map(|(i, line)| (i, line.trim()))
+		.filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+		.map(|(i, line)| line.parse().map_err(|e: String| GapsError::parse(filename, Some(i + 1), &e)))
+		.collect()
+}
+
+/// Generates count random patterns with the given weight (number of match positions) and length.
+/// The first and last position are always match positions, so all patterns have the full length.
+pub fn random(count: usize, weight: usize, length: usize, seed: u64) -> Result<Vec<Pattern>, GapsError> {
+	if weight == 0 || weight > length || weight > MAX_WEIGHT || (weight == 1 && length > 1) {
+		return Err(GapsError::Invalid(format!("Can't generate patterns of weight {} and length {}", weight, length)));
+	}
+
+	let mut rng = StdRng::seed_from_u64(seed);
+	let mut result = Vec::with_capacity(count);
+	for _ in 0..count {
+		let mut pattern = vec![b'0'; length];
+		pattern[0] = b'1';
+		pattern[length - 1] = b'1';
+		if length > 2 {
+			for i in index::sample(&mut rng, length - 2, weight.saturating_sub(2)) {
+				pattern[i + 1] = b'1';
+			}
+		}
+		result.push(Pattern { text: Arc::from(String::from_utf8(pattern).unwrap()), weight });
+	}
+
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn random_patterns_have_weight_and_length() {
+		let patterns = random(20, 5, 9, 1).unwrap();
+		assert_eq!(patterns.len(), 20);
+		for pattern in &patterns {
+			assert_eq!(pattern.len(), 9);
+			assert_eq!(pattern.as_str().matches('1').count(), 5);
+			assert_eq!(pattern.weight(), 5);
+			assert!(pattern.is_match(0) && pattern.is_match(8));
+		}
+		assert_eq!(random(20, 5, 9, 1).unwrap(), patterns);
+
+		assert!(random(1, 10, 9, 1).is_err());
+		assert_eq!(random(1, 2, 2, 1).unwrap()[0].as_str(), "11");
+	}
+
+	#[test]
+	fn patterns_are_validated() {
+		let pattern = "11*01**1".parse::<Pattern>().unwrap();
+		assert_eq!((pattern.as_str(), pattern.len(), pattern.weight()), ("11001001", 8, 4));
+		assert_eq!(pattern.runs(), vec![(0, 2), (4, 1), (7, 1)]);
+
+		assert!("11x1".parse::<Pattern>().is_err());
+		assert!("000".parse::<Pattern>().is_err());
+		assert!("1".repeat(MAX_WEIGHT + 1).parse::<Pattern>().is_err());
+		assert!("1".repeat(MAX_WEIGHT).parse::<Pattern>().is_ok());
+
+		let patterns = parse_list("1111, 11*11,,").unwrap();
+		assert_eq!(patterns.iter().map(Pattern::as_str).collect::<Vec<_>>(), vec!["1111", "11011"]);
+		assert!(parse_list("1111,12").is_err());
+	}
+}
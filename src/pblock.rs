@@ -1,125 +1,536 @@
-use crate::{Sequence, SpacedWord, QTree};
-use std::collections::HashMap;
+use crate::{Sequence, SpacedWord, Pattern, GapsError};
+use crate::sequence::base_code;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::iter::Enumerate;
 use std::ops::Index;
-use smallvec::SmallVec;
 
 #[derive(Debug, Clone)]
-pub struct PBlock(pub Vec<SpacedWord>);
+pub struct PBlock {
+	pub words: Vec<SpacedWord>,
+	// pattern with which the block was found (None for blocks from the input file)
+	pub pattern: Option<Pattern>,
+	// score of the words of a found block (see PBlock::match_score)
+	pub score: Option<i64>,
+	// score of the don't care positions of a found block, or of an input block if it is scored (see
+	// PBlock::dont_care_score)
+	pub dont_care_score: Option<i64>,
+}
+
+/// Block that matches a reference block (see PBlock::find_candidate_blocks)
+#[derive(Debug, Clone)]
+pub struct Candidate {
+	pub block: PBlock,
+	// offset of each word from the corresponding word of the reference block on the same strand
+	// (negative upstream; same order as the words)
+	pub offsets: Vec<i64>,
+}
+
+impl Candidate {
+	/// Sum of the absolute offsets of all words
+	pub fn distance(&self) -> i64 {
+		self.offsets.iter().map(|offset| offset.abs()).sum()
+	}
+}
+
+/// Region around a reference block in which matching blocks are searched: upstream bases before and
+/// downstream bases after its position (on the strand of each word)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchWindow {
+	pub upstream: i64,
+	pub downstream: i64,
+}
+
+/// How much the spaced words of a new block may differ from each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+	// number of match positions at which each word may differ from the word of the first sequence
+	pub mismatches: usize,
+	// minimum score of a new block (see PBlock::match_score)
+	pub min_score: Option<i64>,
+	// minimum score of the don't care positions of a new block (see PBlock::dont_care_score)
+	pub min_dont_care_score: Option<i64>,
+}
+
+impl Tolerance {
+	/// All words have the same bases at the match positions
+	pub const EXACT: Tolerance = Tolerance { mismatches: 0, min_score: None, min_dont_care_score: None };
+}
+
+/// Limits of the indel length of pairs and quartets (see PBlock::indel_length)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndelRange {
+	pub min: Option<usize>,
+	pub max: Option<usize>,
+}
+
+impl IndelRange {
+	/// Every pair is accepted
+	pub const ANY: IndelRange = IndelRange { min: None, max: None };
+
+	/// Returns true if the indel length of the pair lies within the limits. Without limits, pairs that
+	/// have no indel length are accepted as well.
+	pub fn accepts(&self, a: &PBlock, b: &PBlock) -> bool {
+		if *self == IndelRange::ANY {
+			return true;
+		}
+
+		match PBlock::indel_length(a, b) {
+			Some(length) => self.min.map_or(true, |min| length >= min as i64)
+				&& self.max.map_or(true, |max| length <= max as i64),
+			None => false
+		}
+	}
+}
+
+// Which of the matching blocks are used (see PBlock::find_matching_blocks)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchPolicy {
+	// the first matches in the search window of the first sequence
+	First,
+	// the matches with the smallest distance to the block
+	Closest,
+	// all matches
+	All,
+}
 
 impl PBlock {
 	pub fn from_spaced_words(mut input: Vec<SpacedWord>) -> PBlock {
 		input.sort_unstable_by(|a, b| a.seq_name.cmp(&b.seq_name));
-		PBlock(input)
+		PBlock { words: input, pattern: None, score: None, dont_care_score: None }
 	}
 
-	pub fn read_from_file(filename: &str) -> Vec<PBlock> {
-		let mut words: Vec<SpacedWord> = Vec::new();
+	pub fn read_from_file(filename: &str, sequences: &HashMap<String, Sequence>) -> Result<Vec<PBlock>, GapsError> {
+		PBlock::reader(filename, sequences)?.collect()
+	}
 
-		needletail::parse_sequence_path(
-			filename,
-	        |_| {},
-	        |seq| {
-	            let header = String::from_utf8(seq.id.into_owned()).unwrap();
-	            let parts: SmallVec<[&str; 5]> = header.split(' ').collect();
+	/// Returns an iterator that reads the blocks of a file one after another. Positions on the reverse
+	/// strand are converted to forward strand positions (see SpacedWord::position), which requires the
+	/// sequences of the blocks.
+	pub fn reader<'a>(filename: &str, sequences: &'a HashMap<String, Sequence>) -> Result<BlockReader<'a>, GapsError> {
+		let f = File::open(filename).map_err(|e| GapsError::io(filename, e))?;
+		Ok(BlockReader {
+			filename: filename.to_string(),
+			lines: BufReader::new(f).lines().enumerate(),
+			sequences,
+			failed: false,
+			verify: false,
+			verify_pattern: None,
+			drop_mismatches: false,
+			mismatches: 0,
+			reported: Vec::new(),
+			min_score: None,
+			score_pattern: None,
+			low_scores: 0
+		})
+	}
 
-				words.push(SpacedWord::new(parts[0], parts[2].parse().unwrap(), &None, &None, parts[4] == "1)").unwrap());
-	        },
-	    )
-	    .expect("parsing of block file failed");
+	// Checks a block that starts at the given line number
+	fn validate(block: PBlock, filename: &str, line: usize) -> Result<PBlock, GapsError> {
+		if block.len() < 4 {
+			return Err(GapsError::parse(filename, Some(line), &format!("Block contains only {} sequences (at least 4 are needed)", block.len())));
+		}
 
-		if words.len() % 4 != 0 {
-			panic!("Number of spaced words in input file is not divisible by 4.");
+		let mut names = block.get_sequence_names();
+		names.dedup();
+		if names.len() != block.len() {
+			return Err(GapsError::parse(filename, Some(line), "Block contains the same sequence more than once"));
 		}
 
-		words.chunks(4)
-			.map(|chunk| PBlock::from_spaced_words(chunk.to_owned()))
-			.collect()
+		Ok(block)
 	}
 
-	pub fn get_sequence_names(&self) -> Vec<&String> {
-		let mut result = self.0.iter()
-			.map(|word| &word.seq_name)
+	// Parses a header of the form "name (Pos: N RevComp: 0|1)" (without leading '>'). The sequence
+	// name may contain whitespace, RevComp is optional and defaults to 0.
+	fn parse_header(header: &str) -> Result<(String, i64, bool), String> {
+		let header = header.trim();
+
+		let (name, fields) = match (header.rfind('('), header.strip_suffix(')')) {
+			(Some(open), Some(rest)) => (header[..open].trim(), &rest[open+1..]),
+			_ => return Err(format!("Expected \"name (Pos: <position> RevComp: <0 or 1>)\", found \"{}\"", header))
+		};
+
+		if name.is_empty() {
+			return Err(String::from("Missing sequence name"));
+		}
+
+		let mut position = None;
+		let mut rev_comp = false;
+
+		let tokens = fields.split(|c: char| c.is_whitespace() || c == ':')
+			.filter(|token| !token.is_empty())
+			.collect::<Vec<_>>();
+
+		for field in tokens.chunks(2) {
+			match field {
+				["Pos", value] => position = Some(value.parse::<u64>()
+					.map_err(|_| format!("Invalid position \"{}\"", value))? as i64),
+				["RevComp", "0"] => rev_comp = false,
+				["RevComp", "1"] => rev_comp = true,
+				["RevComp", value] => return Err(format!("Invalid RevComp value \"{}\" (should be 0 or 1)", value)),
+				[key, _] => return Err(format!("Unknown field \"{}\"", key)),
+				[key] => return Err(format!("Missing value for field \"{}\"", key)),
+				_ => unreachable!()
+			}
+		}
+
+		match position {
+			Some(position) => Ok((name.to_string(), position, rev_comp)),
+			None => Err(String::from("Missing field \"Pos\""))
+		}
+	}
+
+	pub fn get_sequence_names(&self) -> Vec<&str> {
+		let mut result = self.words.iter()
+			.map(|word| &*word.seq_name)
 			.collect::<Vec<_>>();
 		result.sort_unstable();
 		result
 	}
 
-	/// Returns true if block1 and block2 would form a pair that strongly supports a topology
+	/// Returns true if block1 and block2 would form a pair that strongly supports a topology, i.e. the
+	/// sequences fall into at least two groups of equal gap sizes and each group has two or more members
 	pub fn strong_pair(block1: &PBlock, block2: &PBlock) -> bool {
-		if block1.len() != 4 || block2.len() != 4 || block1.len() != block2.len() {
+		if block1.len() < 4 || block1.len() != block2.len() {
 			return false;
 		}
 
-		let mut d = Vec::new();
-		for i in 0..block1.0.len() {
-			d.push(block1[i].position as i64 - block2[i].position as i64);
+		let classes = PBlock::gap_classes(block1, block2);
+		classes.len() >= 2 && classes.iter().all(|class| class.len() >= 2)
+	}
+
+	/// Returns true if at least one quartet of the pair supports a topology (strongly or weakly)
+	pub fn informative_pair(block1: &PBlock, block2: &PBlock) -> bool {
+		if block1.len() < 4 || block1.len() != block2.len() {
+			return false;
 		}
 
-		!(d[0] == d[1] && d[1] == d[2] && d[2] == d[3]) && (
-		(d[0] == d[1] && d[2] == d[3]) ||
-		(d[0] == d[2] && d[1] == d[3]) ||
-		(d[0] == d[3] && d[2] == d[1]) )
+		PBlock::gap_classes(block1, block2).iter()
+			.any(|class| class.len() >= 2 && block1.len() - class.len() >= 2)
 	}
 
-	pub fn get_distances(a: &PBlock, b: &PBlock) -> HashMap<String, i64> {
-		let mut result = HashMap::new();
-		for i in 0..a.0.len() {
-			result.insert(a[i].seq_name.clone(), b[i].position as i64 - a[i].position as i64);
+	/// Returns true if the word of block2 lies on the same side of the word of block1 in every sequence,
+	/// i.e. all downstream or all upstream (see SpacedWord::gap). Otherwise, the gap sizes would mix
+	/// both directions and the pair wouldn't describe an indel.
+	pub fn same_direction(block1: &PBlock, block2: &PBlock) -> bool {
+		let gaps = (0..block1.len())
+			.map(|i| block1[i].gap(&block2[i]))
+			.collect::<Vec<i64>>();
+		gaps.iter().all(|gap| *gap > 0) || gaps.iter().all(|gap| *gap < 0)
+	}
+
+	/// Returns the gap size of each sequence. Gaps are measured from the upstream to the downstream
+	/// block of the pair (the one with the larger sum of positions), so that they don't depend on the
+	/// direction in which the second block was found.
+	pub fn distances(a: &PBlock, b: &PBlock) -> Vec<i64> {
+		let d = (0..a.len())
+			.map(|i| a[i].gap(&b[i]))
+			.collect::<Vec<i64>>();
+
+		if d.iter().sum::<i64>() < 0 {
+			d.into_iter().map(|x| -x).collect()
+		}
+		else {
+			d
 		}
-		result
 	}
 
-	pub fn find_matching_block(block: &PBlock, sequences: &HashMap<String, Sequence>, pattern: &str, range: i64) -> Option<PBlock> {
-		let sequences = block.get_sequence_names().iter()
-			.map(|name| &sequences[*name])
+	/// Groups the indices of the sequences by their gap size (ordered by gap size)
+	pub fn gap_classes(a: &PBlock, b: &PBlock) -> Vec<Vec<usize>> {
+		let mut d = PBlock::distances(a, b).into_iter()
+			.enumerate()
+			.map(|(i, dist)| (dist, i))
 			.collect::<Vec<_>>();
+		d.sort_unstable();
 
-		let mut spaced_words = Vec::with_capacity(block.len());
-		for i in 0..block.len() {
-			if block[i].rev_comp {
-				spaced_words.push(sequences[i].spaced_words(pattern, -(block[i].position as i64), -(block[i].position as i64) + range, true));
+		let mut result: Vec<Vec<usize>> = Vec::new();
+		for (j, (dist, i)) in d.iter().enumerate() {
+			if j > 0 && d[j-1].0 == *dist {
+				result.last_mut().unwrap().push(*i);
 			}
 			else {
-				spaced_words.push(sequences[i].spaced_words(pattern, block[i].position as i64, block[i].position as i64 + range, false));
+				result.push(vec![*i]);
 			}
+		}
+		result
+	}
+
+	/// Returns the indel length of a pair: the smallest difference between the gap size of a group that
+	/// implies a split (see splits) and the closest other gap size, or None if there is no such group
+	pub fn indel_length(a: &PBlock, b: &PBlock) -> Option<i64> {
+		let d = PBlock::distances(a, b);
+		let classes = PBlock::gap_classes(a, b);
+
+		// The classes are ordered by gap size, so the closest gap sizes are in the neighbouring classes
+		(0..classes.len())
+			.filter(|&k| classes[k].len() >= 2 && a.len() - classes[k].len() >= 2)
+			.flat_map(|k| {
+				let gap = d[classes[k][0]];
+				let before = k.checked_sub(1).map(|j| gap - d[classes[j][0]]);
+				let after = classes.get(k + 1).map(|class| d[class[0]] - gap);
+				before.into_iter().chain(after)
+			})
+			.min()
+	}
+
+	/// Returns the splits implied by the gap sizes of a pair: every group of at least two sequences with
+	/// equal gap size is separated from the remaining sequences of the block (if at least two are left)
+	pub fn splits<'a>(a: &'a PBlock, b: &PBlock) -> Vec<(Vec<&'a str>, Vec<&'a str>)> {
+		PBlock::gap_classes(a, b).into_iter()
+			.filter(|class| class.len() >= 2 && a.len() - class.len() >= 2)
+			.map(|class| {
+				let other = (0..a.len())
+					.filter(|i| !class.contains(i))
+					.map(|i| &*a[i].seq_name)
+					.collect();
+				(class.into_iter().map(|i| &*a[i].seq_name).collect(), other)
+			})
+			.collect()
+	}
+
+	/// Decomposes a pair of n-sequence blocks into the pairs of all four-sequence sub-blocks
+	pub fn quartet_pairs((b1, b2): &(PBlock, PBlock)) -> Vec<(PBlock, PBlock)> {
+		let n = b1.len();
+		let mut result = Vec::new();
+
+		for i in 0..n {
+			for j in i+1..n {
+				for k in j+1..n {
+					for l in k+1..n {
+						let sub = |b: &PBlock| PBlock {
+							words: vec![b[i].clone(), b[j].clone(), b[k].clone(), b[l].clone()],
+							pattern: b.pattern.clone(),
+							score: None,
+							dont_care_score: None
+						};
+						result.push((sub(b1), sub(b2)));
+					}
+				}
+			}
+		}
+
+		result
+	}
+
+	pub fn get_distances(a: &PBlock, b: &PBlock) -> HashMap<String, i64> {
+		a.words.iter().zip(PBlock::distances(a, b))
+			.map(|(word, dist)| (word.seq_name.to_string(), dist))
+			.collect()
+	}
+
+	/// Score of a block of words found with the pattern: the sum over all pairs of words of the number
+	/// of match positions with the same base minus the number of match positions with different bases
+	pub fn match_score(block: &PBlock, pattern: &Pattern) -> i64 {
+		let mut score = 0;
+		for (i, a) in block.words.iter().enumerate() {
+			for b in &block.words[i+1..] {
+				let mismatches = key_mismatches(a.match_positions.unwrap_or(0), b.match_positions.unwrap_or(0));
+				score += pattern.weight() as i64 - 2 * mismatches as i64;
+			}
+		}
+		score
+	}
+
+	/// Multi-SpaM-like score of the don't care positions of a block: the sum over all pairs of words of
+	/// the HOXD70 substitution scores of their bases at the don't care positions of the pattern (all
+	/// positions, if there is no pattern). The words are given by their codes (see Sequence::word_codes),
+	/// symbols other than A/C/G/T get the lowest score.
+	pub fn dont_care_score(words: &[Vec<Option<u64>>], pattern: Option<&Pattern>) -> i64 {
+		let mut score = 0;
+		for (i, a) in words.iter().enumerate() {
+			for b in &words[i+1..] {
+				for (j, (x, y)) in a.iter().zip(b).enumerate() {
+					if pattern.map_or(false, |pattern| pattern.is_match(j)) {
+						continue;
+					}
+					score += match (x, y) {
+						(Some(x), Some(y)) => HOXD70[*x as usize][*y as usize],
+						_ => HOXD70_MIN
+					};
+				}
+			}
+		}
+		score
+	}
+
+	// Computes the scores of a new block (with the sequences of its words in the same order) and checks
+	// them against the minimum scores
+	fn score_block(new_block: &mut PBlock, sequences: &[&Sequence], pattern: &Pattern, tolerance: Tolerance) -> bool {
+		let score = PBlock::match_score(new_block, pattern);
+		let codes = new_block.words.iter().zip(sequences)
+			.map(|(word, sequence)| sequence.word_codes(word.position as usize, word.len, word.rev_comp))
+			.collect::<Vec<_>>();
+		let dont_care_score = PBlock::dont_care_score(&codes, Some(pattern));
+
+		new_block.score = Some(score);
+		new_block.dont_care_score = Some(dont_care_score);
+		tolerance.min_score.map_or(true, |min_score| score >= min_score)
+			&& tolerance.min_dont_care_score.map_or(true, |min_score| dont_care_score >= min_score)
+	}
+
+	/// Searches a block that forms an informative pair with the block (see find_candidate_blocks),
+	/// trying the patterns in order
+	pub fn find_matching_block(block: &PBlock, sequences: &HashMap<String, Sequence>, patterns: &[Pattern], window: SearchWindow, tolerance: Tolerance) -> Result<Option<PBlock>, GapsError> {
+		for pattern in patterns {
+			if let Some(mut new_block) = PBlock::find_matching_block_with(block, sequences, pattern, window, tolerance)? {
+				new_block.pattern = Some(pattern.clone());
+				return Ok(Some(new_block));
+			}
+		}
+
+		Ok(None)
+	}
+
+	fn find_matching_block_with(block: &PBlock, sequences: &HashMap<String, Sequence>, pattern: &Pattern, window: SearchWindow, tolerance: Tolerance) -> Result<Option<PBlock>, GapsError> {
+		let sequences = PBlock::block_sequences(block, sequences)?;
+
+		let mut spaced_words = Vec::with_capacity(block.len());
+		for i in 0..block.len() {
+			spaced_words.push(PBlock::window_words(block, i, sequences[i], pattern, window)?);
 			if i > 0  {
-				spaced_words[i].sort();
+				spaced_words[i].sort_unstable();
 			}
 			else if spaced_words[0].is_empty() {
-				return None;
+				return Ok(None);
 			}
 		}
 
-		for sw in &spaced_words[0] {
-			let mut word_vec = vec![sw.clone()];
-
-			for words in &spaced_words[1..] {
-				if let Ok(search) = words.binary_search(&sw) {
-					// If we find a spaced word more than one time, we throw it away, because we can't
-					// decide, which of them is a match
-					if search < words.len() - 1 && words[search] == words[search + 1]
-						|| search > 0 && words[search] == words[search - 1] {
-						break;
-					}
+		let len = pattern.len();
+		for &(key, position) in &spaced_words[0] {
+			let mut word_vec = vec![SpacedWord::from_key(sequences[0].name.clone(), position, len, key, block[0].rev_comp)];
 
-					word_vec.push(words[search].clone());
-				}
-				else {
-					break;
+			for ((words, sequence), word) in spaced_words[1..].iter().zip(&sequences[1..]).zip(&block.words[1..]) {
+				// If we find a spaced word more than one time, we throw it away, because we can't
+				// decide, which of them is a match
+				match similar_word(words, key, tolerance.mismatches) {
+					Some((found, position)) => word_vec.push(SpacedWord::from_key(sequence.name.clone(), position, len, found, word.rev_comp)),
+					None => break
 				}
 			}
 
-			let new_block = PBlock::from_spaced_words(word_vec);
-			if QTree::new(&block, &new_block).is_some() {
-				return Some(new_block);
+			let mut new_block = PBlock::from_spaced_words(word_vec);
+			if PBlock::informative_pair(block, &new_block) && PBlock::same_direction(block, &new_block) && PBlock::score_block(&mut new_block, &sequences, pattern, tolerance) {
+				return Ok(Some(new_block));
+			}
+		}
+
+		Ok(None)
+	}
+
+	/// Alternative to find_matching_block that uses the candidates of find_candidate_blocks for all
+	/// patterns; the policy decides which of them are returned. With First and Closest, at most limit
+	/// blocks are returned (all, if limit is 0).
+	pub fn find_matching_blocks(block: &PBlock, sequences: &HashMap<String, Sequence>, patterns: &[Pattern], window: SearchWindow, tolerance: Tolerance, policy: MatchPolicy, limit: usize) -> Result<Vec<PBlock>, GapsError> {
+		let mut candidates = Vec::new();
+		for pattern in patterns {
+			for mut candidate in PBlock::find_candidate_blocks(block, sequences, pattern, window, tolerance)? {
+				candidate.block.pattern = Some(pattern.clone());
+				candidates.push(candidate);
+			}
+		}
+
+		if patterns.len() > 1 {
+			// Candidates of all patterns in the order of the window of the first sequence; a block that
+			// is found with several patterns (i.e. starts at the same positions on the strands of the
+			// words) is only kept for the first of them
+			candidates.sort_by_key(|candidate| candidate.offsets[0]);
+			let mut found = HashSet::new();
+			candidates.retain(|candidate| found.insert(candidate.offsets.clone()));
+		}
+
+		if policy == MatchPolicy::Closest {
+			candidates.sort_by_key(Candidate::distance);
+		}
+		if policy != MatchPolicy::All && limit > 0 {
+			candidates.truncate(limit);
+		}
+
+		Ok(candidates.into_iter().map(|candidate| candidate.block).collect())
+	}
+
+	/// Returns all blocks in the search windows that form an informative pair with the block (on the
+	/// same side of it in every sequence, see same_direction), in order of their position in the search
+	/// window of the first sequence. The spaced words of all windows are indexed by their key; every
+	/// key that occurs exactly once in each window forms a candidate block. With mismatches, every key
+	/// that occurs once in the first window and has exactly one similar word in each other window forms
+	/// a candidate block (the other windows are searched linearly).
+	pub fn find_candidate_blocks(block: &PBlock, sequences: &HashMap<String, Sequence>, pattern: &Pattern, window: SearchWindow, tolerance: Tolerance) -> Result<Vec<Candidate>, GapsError> {
+		let sequences = PBlock::block_sequences(block, sequences)?;
+
+		// Position of each key in each window (None, if the key occurs more than once)
+		let mut index: Vec<HashMap<u128, Option<i64>>> = Vec::with_capacity(block.len());
+		let mut window_words = Vec::with_capacity(block.len());
+		for (i, sequence) in sequences.iter().enumerate() {
+			let words = PBlock::window_words(block, i, sequence, pattern, window)?;
+			if words.is_empty() {
+				return Ok(Vec::new());
+			}
+
+			let mut positions = HashMap::with_capacity(words.len());
+			for &(key, position) in &words {
+				positions.entry(key)
+					.and_modify(|p| *p = None)
+					.or_insert(Some(position));
+			}
+			index.push(positions);
+			window_words.push(words);
+		}
+
+		let mut matches = index[0].iter()
+			.filter_map(|(key, position)| {
+				let mut words = vec![(*key, (*position)?)];
+				for i in 1..block.len() {
+					words.push(if tolerance.mismatches == 0 {
+						(*key, (*index[i].get(key)?)?)
+					}
+					else {
+						similar_word(&window_words[i], *key, tolerance.mismatches)?
+					});
+				}
+				// Order of the window, i.e. decreasing forward positions on the reverse strand
+				let order = if block[0].rev_comp { -words[0].1 } else { words[0].1 };
+				Some((order, words))
+			})
+			.collect::<Vec<_>>();
+		matches.sort_unstable();
+
+		let mut result = Vec::new();
+		for (_, keys) in matches {
+			let words = keys.iter().zip(&sequences).zip(&block.words)
+				.map(|((&(key, position), sequence), word)| SpacedWord::from_key(sequence.name.clone(), position, pattern.len(), key, word.rev_comp))
+				.collect();
+			let mut new_block = PBlock::from_spaced_words(words);
+			if PBlock::informative_pair(block, &new_block) && PBlock::same_direction(block, &new_block) && PBlock::score_block(&mut new_block, &sequences, pattern, tolerance) {
+				result.push(Candidate {
+					offsets: block.words.iter().zip(&new_block.words).map(|(a, b)| a.gap(b)).collect(),
+					block: new_block
+				});
 			}
 		}
 
-		None
+		Ok(result)
+	}
+
+	// Returns the sequences of the block in the same order as the block
+	fn block_sequences<'a>(block: &PBlock, sequences: &'a HashMap<String, Sequence>) -> Result<Vec<&'a Sequence>, GapsError> {
+		block.get_sequence_names().iter()
+			.map(|name| sequences.get(*name).ok_or_else(|| GapsError::MissingSequence {
+				name: name.to_string(),
+				location: String::from("FASTA file")
+			}))
+			.collect()
+	}
+
+	// Returns the spaced words of the search window around the i-th word of the block (on the strand
+	// of the word)
+	fn window_words(block: &PBlock, i: usize, sequence: &Sequence, pattern: &Pattern, window: SearchWindow) -> Result<Vec<(u128, i64)>, GapsError> {
+		let anchor = block[i].strand_position(sequence.len());
+		sequence.spaced_words(pattern, anchor - window.upstream, anchor + window.downstream, block[i].rev_comp)
 	}
 
 	pub fn len(&self) -> usize {
-		self.0.len()
+		self.words.len()
 	}
 
 	pub fn is_empty(&self) -> bool {
@@ -129,6 +540,18 @@ impl PBlock {
 	pub fn pair_to_string((b1, b2): &(PBlock, PBlock)) -> String {
 		let mut s = String::new();
 
+		if let Some(pattern) = &b2.pattern {
+			s = format!("{}Pattern: {}\n", s, pattern);
+		}
+		if let Some(score) = b2.score {
+			s = format!("{}Score: {}\n", s, score);
+		}
+		if let Some(score) = b2.dont_care_score {
+			s = format!("{}Don't care score: {}\n", s, score);
+		}
+		if let Some(length) = PBlock::indel_length(b1, b2) {
+			s = format!("{}Indel length: {}\n", s, length);
+		}
 		for i in 0..b1.len() {
 			s = format!("{}{} {}\t{} {}\n", s, b1[i].seq_name, b1[i].position, b2[i].seq_name, b2[i].position);
 		}
@@ -138,10 +561,721 @@ impl PBlock {
 	}
 }
 
+// Number of mismatching blocks that are described by BlockReader::reported_mismatches
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+/// Iterator over the blocks of a file (blocks are separated by blank lines)
+pub struct BlockReader<'a> {
+	filename: String,
+	lines: Enumerate<Lines<BufReader<File>>>,
+	sequences: &'a HashMap<String, Sequence>,
+	failed: bool,
+	verify: bool,
+	// only the match positions of this pattern are compared when verifying
+	verify_pattern: Option<Pattern>,
+	drop_mismatches: bool,
+	mismatches: usize,
+	reported: Vec<String>,
+	// input blocks with a lower score of their don't care positions are dropped
+	min_score: Option<i64>,
+	// don't care positions of the input blocks (all positions, if None)
+	score_pattern: Option<Pattern>,
+	low_scores: usize,
+}
+
+impl BlockReader<'_> {
+	/// Checks the spaced words of each block against the sequences at their positions. Only the match
+	/// positions of the pattern are compared, if one is given. Blocks with a word that doesn't match
+	/// (or lies outside of its sequence) are counted and dropped if drop_mismatches is set.
+	pub fn verify(mut self, pattern: Option<Pattern>, drop_mismatches: bool) -> Self {
+		self.verify = true;
+		self.verify_pattern = pattern;
+		self.drop_mismatches = drop_mismatches;
+		self
+	}
+
+	/// Number of blocks read so far whose words don't match the sequences (see BlockReader::verify)
+	pub fn mismatches(&self) -> usize {
+		self.mismatches
+	}
+
+	/// Descriptions of the first mismatching blocks (with file and line)
+	pub fn reported_mismatches(&self) -> &[String] {
+		&self.reported
+	}
+
+	/// Drops blocks whose spaced words have a lower score than min_score at the don't care positions of
+	/// the pattern (see PBlock::dont_care_score)
+	pub fn min_score(mut self, min_score: i64, pattern: Option<Pattern>) -> Self {
+		self.min_score = Some(min_score);
+		self.score_pattern = pattern;
+		self
+	}
+
+	/// Number of blocks read so far that were dropped because of their score (see BlockReader::min_score)
+	pub fn low_scores(&self) -> usize {
+		self.low_scores
+	}
+
+	fn read_block(&mut self) -> Result<Option<PBlock>, GapsError> {
+		loop {
+			let words = self.read_words()?;
+			if words.is_empty() {
+				return Ok(None);
+			}
+			let first_line = words[0].0;
+
+			let mut mismatch = None;
+			let mut block = Vec::with_capacity(words.len());
+			let mut texts = Vec::with_capacity(words.len());
+			for (line, word, text) in words {
+				let word = self.to_forward_strand(word).map_err(|msg| GapsError::parse(&self.filename, Some(line), &msg))?;
+				if mismatch.is_none() {
+					mismatch = self.check_word(&word, &text).err().map(|msg| GapsError::parse(&self.filename, Some(line), &msg));
+				}
+				block.push(word);
+				texts.push((line, text));
+			}
+
+			// Blocks are validated before verification, so that malformed blocks are still errors
+			let mut block = PBlock::validate(PBlock::from_spaced_words(block), &self.filename, first_line)?;
+
+			if let Some(mismatch) = mismatch {
+				self.mismatches += 1;
+				if self.reported.len() < MAX_REPORTED_MISMATCHES {
+					self.reported.push(mismatch.to_string());
+				}
+				if self.drop_mismatches {
+					continue;
+				}
+			}
+
+			if let Some(min_score) = self.min_score {
+				let score = self.score_words(&texts)?;
+				block.dont_care_score = Some(score);
+				if score < min_score {
+					self.low_scores += 1;
+					continue;
+				}
+			}
+
+			return Ok(Some(block));
+		}
+	}
+
+	// Don't care score of the spaced words of a block (with the line numbers of their headers)
+	fn score_words(&self, texts: &[(usize, String)]) -> Result<i64, GapsError> {
+		let pattern = self.score_pattern.as_ref();
+		let mut words = Vec::with_capacity(texts.len());
+		for (line, text) in texts {
+			if let Some(pattern) = pattern.filter(|pattern| pattern.len() != text.len()) {
+				return Err(GapsError::parse(&self.filename, Some(*line), &format!("Spaced word has length {}, but the pattern has length {}", text.len(), pattern.len())));
+			}
+			words.push(text.bytes().map(base_code).collect::<Vec<_>>());
+		}
+
+		Ok(PBlock::dont_care_score(&words, pattern))
+	}
+
+	// Reads the words of the next block with the line number of their header and their text (positions
+	// as given in the file), no words at the end of the file
+	fn read_words(&mut self) -> Result<Vec<(usize, SpacedWord, String)>, GapsError> {
+		let filename = &self.filename;
+		let mut words: Vec<(usize, SpacedWord, String)> = Vec::new();
+
+		for (i, line) in &mut self.lines {
+			let line = line.map_err(|e| GapsError::io(filename, e))?;
+			let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+
+			if let Some(header) = line.strip_prefix('>') {
+				let (name, position, rev_comp) = PBlock::parse_header(header)
+					.map_err(|msg| GapsError::parse(filename, Some(i + 1), &msg))?;
+				// The sequence name is shared with all other words of the sequence
+				let sequence = self.sequences.get(&name).ok_or_else(|| GapsError::parse(filename, Some(i + 1),
+					&format!("Sequence \"{}\" not found in FASTA file", name)))?;
+				words.push((i + 1, SpacedWord::new(sequence.name.clone(), position, 0, rev_comp), String::new()));
+			}
+			else if line.trim().is_empty() {
+				if !words.is_empty() {
+					break;
+				}
+			}
+			else if let Some((_, word, text)) = words.last_mut() {
+				// The spaced word may span several lines
+				word.len += line.trim().chars().count();
+				if self.verify || self.min_score.is_some() {
+					text.push_str(line.trim());
+				}
+			}
+			else {
+				return Err(GapsError::parse(filename, Some(i + 1), "Expected a header starting with '>'"));
+			}
+		}
+
+		Ok(words)
+	}
+
+	// Compares a word (with forward strand position) with its sequence, if the words are verified
+	fn check_word(&self, word: &SpacedWord, text: &str) -> Result<(), String> {
+		if !self.verify {
+			return Ok(());
+		}
+		let sequence = &self.sequences[&*word.seq_name];
+		let pattern = self.verify_pattern.as_ref();
+
+		if word.len == 0 {
+			return Err(String::from("Missing spaced word"));
+		}
+		if let Some(pattern) = pattern.filter(|pattern| pattern.len() != word.len) {
+			return Err(format!("Spaced word has length {}, but the pattern has length {}", word.len, pattern.len()));
+		}
+		if word.position < 0 || !sequence.matches_word(text, word.position as usize, word.rev_comp, pattern) {
+			return Err(format!("Spaced word doesn't match sequence \"{}\" at forward strand position {}{}",
+				word.seq_name, word.position, if word.rev_comp { " (reverse strand)" } else { "" }));
+		}
+
+		Ok(())
+	}
+
+	// Multi-SpaM reports the position of the first base on the strand of the word, i.e. for the reverse
+	// strand the position in the reverse complement of the sequence
+	fn to_forward_strand(&self, mut word: SpacedWord) -> Result<SpacedWord, String> {
+		let seq_len = self.sequences[&*word.seq_name].len() as i64;
+
+		if word.rev_comp {
+			if word.len == 0 {
+				return Err(String::from("Missing spaced word (its length is needed for positions on the reverse strand)"));
+			}
+			word.position = seq_len - word.position - word.len as i64;
+		}
+
+		// When verifying, such words are mismatches instead (see BlockReader::check_word)
+		if !self.verify && (word.position < 0 || word.position + word.len as i64 > seq_len) {
+			return Err(format!("Position is outside of sequence \"{}\" (length {})", word.seq_name, seq_len));
+		}
+
+		Ok(word)
+	}
+}
+
+impl Iterator for BlockReader<'_> {
+	type Item = Result<PBlock, GapsError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+
+		let result = self.read_block().transpose();
+		self.failed = matches!(result, Some(Err(_)));
+		result
+	}
+}
+
+// HOXD70 substitution matrix (A, C, G, T) as used by Multi-SpaM
+const HOXD70: [[i64; 4]; 4] = [
+	[91, -114, -31, -123],
+	[-114, 100, -125, -31],
+	[-31, -125, 100, -114],
+	[-123, -31, -114, 91]
+];
+const HOXD70_MIN: i64 = -125;
+
+// Number of match positions with different bases in two keys (see Sequence::spaced_words)
+fn key_mismatches(a: u128, b: u128) -> usize {
+	let x = a ^ b;
+	((x | (x >> 1)) & (u128::MAX / 3)).count_ones() as usize
+}
+
+// Returns the only word in the window that differs from the key in at most mismatches match positions
+// (None, if there is no such word or more than one). Without mismatches, the words have to be sorted.
+fn similar_word(words: &[(u128, i64)], key: u128, mismatches: usize) -> Option<(u128, i64)> {
+	if mismatches == 0 {
+		let start = words.partition_point(|word| word.0 < key);
+		let end = words.partition_point(|word| word.0 <= key);
+		return if end - start == 1 { Some(words[start]) } else { None };
+	}
+
+	let mut similar = words.iter().filter(|word| key_mismatches(word.0, key) <= mismatches);
+	match (similar.next(), similar.next()) {
+		(Some(word), None) => Some(*word),
+		_ => None
+	}
+}
+
 impl Index<usize> for PBlock {
 	type Output = SpacedWord;
 
 	fn index(&self, i: usize) -> &Self::Output {
-		&self.0[i]
+		&self.words[i]
+	}
+}
+
+#[cfg(test)]
+impl PBlock {
+	/// Pair of blocks of the named sequences (words of length 10 on the forward strand) at the start
+	/// position and at the start plus the gap size of each sequence
+	pub(crate) fn pair_with_gaps(names: &[&str], start: i64, gaps: &[i64]) -> (PBlock, PBlock) {
+		let block = |offsets: &[i64]| PBlock::from_spaced_words(names.iter().zip(offsets)
+			.map(|(name, offset)| SpacedWord::new((*name).into(), start + offset, 10, false))
+			.collect());
+		(block(&vec![0; names.len()]), block(gaps))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+	use std::io::Write;
+	use std::sync::Arc;
+	use crate::QTree;
+
+	const ANCHOR: usize = 50;
+	const WORD_LEN: usize = 12;
+	const INDEL: usize = 120;
+
+	fn rev_comp(seq: &str) -> String {
+		seq.chars().rev()
+			.map(|c| match c { 'A' => 'T', 'C' => 'G', 'G' => 'C', 'T' => 'A', c => c })
+			.collect()
+	}
+
+	// A and B have an insertion of three bases downstream of the anchor, C and D don't. Sequences
+	// with reverse set are stored as reverse complement.
+	fn synthetic_sequences(reverse: &[bool; 4]) -> Vec<(&'static str, String)> {
+		let mut rng = StdRng::seed_from_u64(1);
+		let base = (0..300).map(|_| ['A', 'C', 'G', 'T'][rng.gen_range(0..4)]).collect::<String>();
+		let inserted = format!("{}TTG{}", &base[..INDEL], &base[INDEL..]);
+
+		["A", "B", "C", "D"].iter().zip(reverse)
+			.map(|(name, reverse)| {
+				let seq = if *name == "A" || *name == "B" { inserted.clone() } else { base.clone() };
+				(*name, if *reverse { rev_comp(&seq) } else { seq })
+			})
+			.collect()
+	}
+
+	// Writes a block with the anchor word of each sequence in Multi-SpaM format
+	fn write_block(test: &str, seqs: &[(&str, String)], reverse: &[bool; 4]) -> String {
+		let filename = std::env::temp_dir()
+			.join(format!("gaps_rs_test_{}_{}", test, std::process::id()))
+			.to_string_lossy()
+			.into_owned();
+
+		let mut f = File::create(&filename).unwrap();
+		for ((name, seq), reverse) in seqs.iter().zip(reverse) {
+			// Positions on the reverse strand refer to the reverse complement, which is the original
+			// sequence for the sequences that are stored as reverse complement
+			let strand = if *reverse { rev_comp(seq) } else { seq.clone() };
+			writeln!(f, ">{} (Pos: {} RevComp: {})\n{}", name, ANCHOR, *reverse as u8, &strand[ANCHOR..ANCHOR + WORD_LEN]).unwrap();
+		}
+		filename
+	}
+
+	fn key_of(seq: &Sequence, position: i64) -> u128 {
+		seq.spaced_words(&"11111".parse().unwrap(), 0, seq.len() as i64, false).unwrap().into_iter()
+			.find(|word| word.1 == position)
+			.unwrap().0
+	}
+
+	fn to_map(seqs: &[(&str, String)]) -> HashMap<String, Sequence> {
+		seqs.iter()
+			.map(|(name, seq)| (name.to_string(), Sequence::new(name, seq.as_bytes())))
+			.collect()
+	}
+
+	fn read_block(test: &str, reverse: &[bool; 4]) -> (PBlock, HashMap<String, Sequence>) {
+		let seqs = synthetic_sequences(reverse);
+		let sequences = to_map(&seqs);
+		let filename = write_block(test, &seqs, reverse);
+		let blocks = PBlock::read_from_file(&filename, &sequences);
+		std::fs::remove_file(&filename).unwrap();
+
+		let mut blocks = blocks.unwrap();
+		assert_eq!(blocks.len(), 1);
+		(blocks.remove(0), sequences)
+	}
+
+	fn find_pair(test: &str, reverse: &[bool; 4]) -> (PBlock, PBlock) {
+		let (block, sequences) = read_block(test, reverse);
+		let window = SearchWindow { upstream: 0, downstream: 200 };
+		let patterns = ["11111111".parse().unwrap()];
+		let matching = PBlock::find_matching_block(&block, &sequences, &patterns, window, Tolerance::EXACT).unwrap().unwrap();
+
+		let candidates = PBlock::find_matching_blocks(&block, &sequences, &patterns, window, Tolerance::EXACT, MatchPolicy::First, 1).unwrap();
+		assert_eq!(candidates.len(), 1);
+		assert_eq!(PBlock::distances(&block, &candidates[0]), PBlock::distances(&block, &matching));
+
+		(block, matching)
+	}
+
+	#[test]
+	fn reverse_positions_are_converted_to_forward_strand() {
+		let reverse = [false, true, false, true];
+		let (block, _) = read_block("convert", &reverse);
+
+		for word in &block.words {
+			assert_eq!(word.len, WORD_LEN);
+			assert_eq!(word.rev_comp, word.seq_name.as_ref() == "B" || word.seq_name.as_ref() == "D");
+		}
+		assert_eq!(block[0].position, ANCHOR as i64);
+		assert_eq!(block[1].position, (303 - ANCHOR - WORD_LEN) as i64);
+		assert_eq!(block[1].strand_position(303), ANCHOR as i64);
+		assert_eq!(block[3].position, (300 - ANCHOR - WORD_LEN) as i64);
+	}
+
+	#[test]
+	fn reverse_positions_need_word_length() {
+		let seqs = synthetic_sequences(&[true; 4]);
+		let sequences = to_map(&seqs);
+		let filename = std::env::temp_dir()
+			.join(format!("gaps_rs_test_no_words_{}", std::process::id()));
+		std::fs::write(&filename, ">A (Pos: 10 RevComp: 1)\n>B (Pos: 10 RevComp: 1)\n>C (Pos: 10 RevComp: 1)\n>D (Pos: 10 RevComp: 1)\n").unwrap();
+
+		let result = PBlock::read_from_file(&filename.to_string_lossy(), &sequences);
+		std::fs::remove_file(&filename).unwrap();
+		assert!(matches!(result, Err(GapsError::Parse { line: Some(1), .. })));
+	}
+
+	#[test]
+	fn headers_are_parsed() {
+		assert_eq!(PBlock::parse_header("A (Pos: 10 RevComp: 1)"), Ok((String::from("A"), 10, true)));
+		assert_eq!(PBlock::parse_header("A\t(Pos:\t10\tRevComp:\t0)\r"), Ok((String::from("A"), 10, false)));
+		assert_eq!(PBlock::parse_header("A (Pos: 10)"), Ok((String::from("A"), 10, false)));
+		assert_eq!(PBlock::parse_header("Homo sapiens chr1 (Pos:10 RevComp:1)"), Ok((String::from("Homo sapiens chr1"), 10, true)));
+		assert_eq!(PBlock::parse_header("A (1) (Pos: 10)"), Ok((String::from("A (1)"), 10, false)));
+
+		assert!(PBlock::parse_header("A Pos: 10").is_err());
+		assert!(PBlock::parse_header("(Pos: 10)").is_err());
+		assert!(PBlock::parse_header("A (RevComp: 1)").is_err());
+		assert!(PBlock::parse_header("A (Pos: -1)").is_err());
+		assert!(PBlock::parse_header("A (Pos: 10 RevComp: 2)").is_err());
+		assert!(PBlock::parse_header("A (Pos: 10 Strand: 1)").is_err());
+	}
+
+	#[test]
+	fn relaxed_headers_are_read() {
+		let seqs = synthetic_sequences(&[false; 4]);
+		let names = ["seq A", "seq B", "C", "D"];
+		let sequences = names.iter().zip(&seqs)
+			.map(|(name, (_, seq))| (name.to_string(), Sequence::new(name, seq.as_bytes())))
+			.collect::<HashMap<_, _>>();
+
+		let filename = std::env::temp_dir()
+			.join(format!("gaps_rs_test_headers_{}", std::process::id()));
+		std::fs::write(&filename, ">seq A\t(Pos:\t10\tRevComp:\t0)\r\n>seq B (Pos: 12)\r\n>C\t(Pos: 14 RevComp: 0)\r\n>D (Pos: 16)\r\n\r\n\
+			>seq A (Pos: 20)\r\n>seq B (Pos: 22)\r\n>C Pos: 24\r\n>D (Pos: 26)\r\n").unwrap();
+
+		let mut reader = PBlock::reader(&filename.to_string_lossy(), &sequences).unwrap();
+		let block = reader.next().unwrap().unwrap();
+		let result = reader.next().unwrap();
+		std::fs::remove_file(&filename).unwrap();
+
+		assert_eq!(block.get_sequence_names(), vec!["C", "D", "seq A", "seq B"]);
+		assert_eq!(block.words.iter().map(|word| (word.position, word.rev_comp)).collect::<Vec<_>>(),
+			vec![(14, false), (16, false), (10, false), (12, false)]);
+		// The malformed header is in line 8
+		assert!(matches!(result, Err(GapsError::Parse { line: Some(8), .. })));
+	}
+
+	#[test]
+	fn unknown_sequences_are_reported_with_line() {
+		let seqs = synthetic_sequences(&[false; 4]);
+		let sequences = to_map(&seqs);
+		let filename = std::env::temp_dir()
+			.join(format!("gaps_rs_test_unknown_{}", std::process::id()));
+		std::fs::write(&filename, ">A (Pos: 10)\n>B (Pos: 10)\n>X (Pos: 10)\n>D (Pos: 10)\n").unwrap();
+
+		let result = PBlock::read_from_file(&filename.to_string_lossy(), &sequences);
+		std::fs::remove_file(&filename).unwrap();
+		match result {
+			Err(GapsError::Parse { line: Some(3), msg, .. }) => assert!(msg.contains("\"X\"")),
+			result => panic!("Unexpected result {:?}", result)
+		}
+	}
+
+	#[test]
+	fn mismatching_words_are_dropped() {
+		let reverse = [false, true, false, true];
+		let seqs = synthetic_sequences(&reverse);
+		let sequences = to_map(&seqs);
+		let filename = write_block("verify", &seqs, &reverse);
+
+		// A second block in which the first base of the word of B is changed
+		let block = std::fs::read_to_string(&filename).unwrap();
+		let mut lines = block.lines().map(String::from).collect::<Vec<_>>();
+		let changed = if lines[3].starts_with('A') { "C" } else { "A" };
+		lines[3].replace_range(..1, changed);
+		std::fs::write(&filename, format!("{}\n{}\n", block, lines.join("\n"))).unwrap();
+
+		let mut reader = PBlock::reader(&filename, &sequences).unwrap().verify(None, true);
+		let blocks = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+		assert_eq!((blocks.len(), reader.mismatches()), (1, 1));
+		assert!(reader.reported_mismatches()[0].contains(":12:"));
+
+		// The changed base isn't a match position of the pattern
+		let mut reader = PBlock::reader(&filename, &sequences).unwrap().verify(Some("011111111111".parse().unwrap()), true);
+		let blocks = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+		std::fs::remove_file(&filename).unwrap();
+		assert_eq!((blocks.len(), reader.mismatches()), (2, 0));
+	}
+
+	#[test]
+	fn similar_words_tolerate_mismatches() {
+		let seq = Sequence::new("s", b"ACGTATTGTAACGTTGG");
+		let pattern = "11111".parse::<Pattern>().unwrap();
+		let mut words = seq.spaced_words(&pattern, 0, 17, false).unwrap();
+		let (key, _) = words[0];
+
+		// ACGTA differs from TTGTA (position 5) in two and from ACGTT (position 10) in one match position
+		assert_eq!(key_mismatches(key, words[5].0), 2);
+		assert_eq!(similar_word(&words[1..], key, 1), Some(words[10]));
+		assert_eq!(similar_word(&words[1..], key, 2), None);
+		words.sort_unstable();
+		assert_eq!(similar_word(&words, key, 0), Some((key, 0)));
+
+		let block = PBlock::from_spaced_words(vec![
+			SpacedWord::from_key(Arc::from("a"), 0, 5, key, false),
+			SpacedWord::from_key(Arc::from("b"), 0, 5, key, false),
+			SpacedWord::from_key(Arc::from("c"), 5, 5, key_of(&seq, 5), false)
+		]);
+		// a-b: 5 - 0, a-c and b-c: 3 - 2
+		assert_eq!(PBlock::match_score(&block, &pattern), 7);
+	}
+
+	#[test]
+	fn dont_care_positions_are_scored() {
+		let codes = |word: &str| word.bytes().map(base_code).collect::<Vec<_>>();
+		let words = [codes("ACGT"), codes("ACGA"), codes("NCGT")];
+		let pattern = "0110".parse::<Pattern>().unwrap();
+
+		// Position 0: A/A, A/N, A/N; position 3: T/A, T/T, A/T
+		assert_eq!(PBlock::dont_care_score(&words, Some(&pattern)), 91 - 2 * 125 - 123 + 91 - 123);
+		assert_eq!(PBlock::dont_care_score(&words[..2], None), 91 + 100 + 100 - 123);
+	}
+
+	#[test]
+	fn input_blocks_below_min_score_are_dropped() {
+		let reverse = [false, true, false, true];
+		let seqs = synthetic_sequences(&reverse);
+		let sequences = to_map(&seqs);
+		let filename = write_block("min_score", &seqs, &reverse);
+
+		// The words of the block are identical, i.e. every pair scores the diagonal of the matrix
+		let mut reader = PBlock::reader(&filename, &sequences).unwrap().min_score(0, None);
+		let blocks = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+		let score = blocks[0].dont_care_score.unwrap();
+		assert!(score > 0);
+
+		let mut reader = PBlock::reader(&filename, &sequences).unwrap().min_score(score + 1, None);
+		let blocks = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+		assert_eq!((blocks.len(), reader.low_scores()), (0, 1));
+
+		let result = PBlock::reader(&filename, &sequences).unwrap().min_score(0, Some("111".parse().unwrap())).collect::<Result<Vec<_>, _>>();
+		std::fs::remove_file(&filename).unwrap();
+		assert!(matches!(result, Err(GapsError::Parse { line: Some(1), .. })));
+	}
+
+	#[test]
+	fn gaps_on_forward_strand() {
+		let (block, matching) = find_pair("forward", &[false; 4]);
+
+		let d = PBlock::distances(&block, &matching);
+		assert!(d[2] > 0);
+		assert_eq!(d, vec![d[2] + 3, d[2] + 3, d[2], d[2]]);
+		assert!(PBlock::strong_pair(&block, &matching));
+		assert_eq!(PBlock::indel_length(&block, &matching), Some(3));
+	}
+
+	#[test]
+	fn upstream_search_finds_the_same_indel() {
+		for reverse in [[false; 4], [true, false, true, false]] {
+			let (block, matching) = find_pair("upstream", &reverse);
+			let seqs = synthetic_sequences(&reverse);
+			let sequences = to_map(&seqs);
+
+			// Searching upstream of the new block finds a block upstream of the indel
+			let window = SearchWindow { upstream: 200, downstream: 0 };
+			let patterns = ["11111111".parse().unwrap()];
+			let found = PBlock::find_matching_blocks(&matching, &sequences, &patterns, window, Tolerance::EXACT, MatchPolicy::All, 0).unwrap();
+			assert!(!found.is_empty());
+			for upstream in &found {
+				assert!((0..4).all(|i| matching[i].gap(&upstream[i]) < 0));
+				assert_eq!(PBlock::gap_classes(upstream, &matching), PBlock::gap_classes(&block, &matching));
+				assert_eq!(PBlock::indel_length(upstream, &matching), Some(3));
+			}
+		}
+	}
+
+	#[test]
+	fn blocks_on_both_sides_are_rejected() {
+		let mut rng = StdRng::seed_from_u64(2);
+		let base = (0..300).map(|_| ['A', 'C', 'G', 'T'][rng.gen_range(0..4)]).collect::<String>();
+		// The same word is upstream of the anchor in A and B, but downstream in C and D
+		let word = "ACGTTGCA";
+		let seqs = ["A", "B", "C", "D"].iter()
+			.map(|name| {
+				let start = if *name < "C" { 30 } else { 150 };
+				(*name, format!("{}{}{}", &base[..start], word, &base[start + word.len()..]))
+			})
+			.collect::<Vec<_>>();
+		let sequences = to_map(&seqs);
+		let block = PBlock::from_spaced_words(["A", "B", "C", "D"].iter()
+			.map(|name| SpacedWord::new(Arc::from(*name), ANCHOR as i64, WORD_LEN, false))
+			.collect());
+
+		let (a, b) = PBlock::pair_with_gaps(&["A", "B", "C", "D"], 100, &[-20, -20, 100, 100]);
+		assert!(PBlock::strong_pair(&a, &b) && !PBlock::same_direction(&a, &b));
+		let (a, b) = PBlock::pair_with_gaps(&["A", "B", "C", "D"], 100, &[0, 0, 100, 100]);
+		assert!(!PBlock::same_direction(&a, &b));
+
+		let window = SearchWindow { upstream: 50, downstream: 200 };
+		let patterns = ["11111111".parse().unwrap()];
+		assert!(PBlock::find_matching_block(&block, &sequences, &patterns, window, Tolerance::EXACT).unwrap().is_none());
+		assert!(PBlock::find_matching_blocks(&block, &sequences, &patterns, window, Tolerance::EXACT, MatchPolicy::All, 0).unwrap().is_empty());
+	}
+
+	// Random sequences A-D with three words planted downstream of the anchor at the given positions
+	// of A/B and C/D: (60, 250), (120, 121) and (200, 203)
+	fn planted_words() -> (PBlock, HashMap<String, Sequence>) {
+		let mut rng = StdRng::seed_from_u64(3);
+		let words = [(60, 250, "ACGTTGCA"), (120, 121, "GGATCCTA"), (200, 203, "TTCAGGAC")];
+		let seqs = ["A", "B", "C", "D"].iter()
+			.map(|name| {
+				let mut seq = (0..400).map(|_| ['A', 'C', 'G', 'T'][rng.gen_range(0..4)]).collect::<String>();
+				for (ab, cd, word) in words {
+					let start = if *name < "C" { ab } else { cd };
+					seq.replace_range(start..start + word.len(), word);
+				}
+				(*name, seq)
+			})
+			.collect::<Vec<_>>();
+
+		let block = PBlock::from_spaced_words(["A", "B", "C", "D"].iter()
+			.map(|name| SpacedWord::new(Arc::from(*name), ANCHOR as i64, WORD_LEN, false))
+			.collect());
+		(block, to_map(&seqs))
 	}
-}
\ No newline at end of file
+
+	// Positions of the new blocks in A and C
+	fn positions(blocks: &[PBlock]) -> Vec<(i64, i64)> {
+		blocks.iter().map(|block| (block[0].position, block[2].position)).collect()
+	}
+
+	#[test]
+	fn matching_blocks_follow_the_policy() {
+		let (block, sequences) = planted_words();
+		let window = SearchWindow { upstream: 0, downstream: 300 };
+		let patterns = ["11111111".parse().unwrap()];
+		let find = |policy, limit| {
+			positions(&PBlock::find_matching_blocks(&block, &sequences, &patterns, window, Tolerance::EXACT, policy, limit).unwrap())
+		};
+
+		let in_window = vec![(60, 250), (120, 121), (200, 203)];
+		assert_eq!(find(MatchPolicy::First, 0), in_window);
+		assert_eq!(find(MatchPolicy::First, 1), in_window[..1]);
+		assert_eq!(find(MatchPolicy::First, 2), in_window[..2]);
+		assert_eq!(find(MatchPolicy::All, 1), in_window);
+
+		// Distances 20 + 400, 140 + 142 and 300 + 306
+		let closest = vec![(120, 121), (60, 250), (200, 203)];
+		assert_eq!(find(MatchPolicy::Closest, 0), closest);
+		assert_eq!(find(MatchPolicy::Closest, 2), closest[..2]);
+
+		let candidates = PBlock::find_candidate_blocks(&block, &sequences, &patterns[0], window, Tolerance::EXACT).unwrap();
+		assert_eq!(candidates.iter().map(Candidate::distance).collect::<Vec<_>>(), vec![420, 282, 606]);
+		assert_eq!(candidates[1].offsets, vec![70, 70, 71, 71]);
+	}
+
+	#[test]
+	fn blocks_found_by_several_patterns_are_kept_once() {
+		let (block, sequences) = planted_words();
+		let window = SearchWindow { upstream: 0, downstream: 300 };
+		let find = |patterns: &[&str]| {
+			let patterns = patterns.iter().map(|pattern| pattern.parse().unwrap()).collect::<Vec<Pattern>>();
+			PBlock::find_matching_blocks(&block, &sequences, &patterns, window, Tolerance::EXACT, MatchPolicy::All, 0).unwrap()
+		};
+
+		let found = find(&["11111111", "11111111"]);
+		assert_eq!(positions(&found), vec![(60, 250), (120, 121), (200, 203)]);
+
+		// The shorter pattern finds the same blocks and the blocks one base further downstream; the
+		// blocks that both patterns find are only kept for the first pattern
+		let found = find(&["11111111", "1111111"]);
+		assert_eq!(positions(&found), vec![(60, 250), (61, 251), (120, 121), (121, 122), (200, 203), (201, 204)]);
+		let lengths = found.iter().map(|block| block.pattern.as_ref().unwrap().len()).collect::<Vec<_>>();
+		assert_eq!(lengths, vec![8, 7, 8, 7, 8, 7]);
+	}
+
+	// Pair of blocks of the sequences s0, s1, ... with the given gap sizes
+	fn indel_pair(gaps: &[i64]) -> (PBlock, PBlock) {
+		PBlock::pair_with_gaps(&["s0", "s1", "s2", "s3", "s4", "s5"][..gaps.len()], 0, gaps)
+	}
+
+	#[test]
+	fn indel_length_is_smallest_difference_of_a_split() {
+		let pair = indel_pair;
+
+		let (a, b) = pair(&[10, 10, 13, 13, 20]);
+		assert_eq!(PBlock::indel_length(&a, &b), Some(3));
+		let (a, b) = pair(&[10, 10, 11, 15]);
+		assert_eq!(PBlock::indel_length(&a, &b), Some(1));
+		assert_eq!(QTree::new(&a, &b, IndelRange::ANY).unwrap().unwrap().indel_length(), 1);
+		// No split: the other group has only one sequence
+		let (a, b) = pair(&[10, 10, 10, 10, 12]);
+		assert_eq!(PBlock::indel_length(&a, &b), None);
+	}
+
+	#[test]
+	fn indel_range_is_applied_to_pairs_and_quartets() {
+		let pairs = [&[10, 10, 13, 13, 20, 20][..], &[10, 10, 11, 15, 15], &[10, 10, 10, 10, 12], &[10, 10, 10, 10, 10]].iter()
+			.map(|gaps| indel_pair(gaps))
+			.collect::<Vec<_>>();
+
+		// Without limits, all pairs and quartets are kept
+		assert!(pairs.iter().all(|(a, b)| IndelRange::ANY.accepts(a, b)));
+		let quartets = |indels| QTree::from_pairs(&pairs, indels).unwrap().len();
+		let all = quartets(IndelRange::ANY);
+		assert_eq!(all, pairs.iter()
+			.flat_map(PBlock::quartet_pairs)
+			.filter(|(a, b)| QTree::new(a, b, IndelRange::ANY).unwrap().is_some())
+			.count());
+
+		let range = IndelRange { min: Some(2), max: Some(5) };
+		let accepted = pairs.iter().map(|(a, b)| range.accepts(a, b)).collect::<Vec<_>>();
+		assert_eq!(accepted, vec![true, false, false, false]);
+
+		// Quartets of the second pair without s2 (or with s2 and only one of s0 and s1) are within the
+		// range, although the pair has indel length 1
+		let trees = QTree::from_pairs(&pairs[1..2], range).unwrap();
+		let lengths = trees.iter().map(|tree| (tree.seq_names(), tree.indel_length())).collect::<Vec<_>>();
+		assert_eq!(lengths, vec![(vec!["s0", "s1", "s3", "s4"], 5), (vec!["s0", "s2", "s3", "s4"], 4), (vec!["s1", "s2", "s3", "s4"], 4)]);
+
+		// The quartets of the first pair with the gap sizes 10 and 20 have indel length 10
+		let trees = QTree::from_pairs(&pairs[..1], range).unwrap();
+		assert!(!trees.is_empty() && trees.iter().all(|tree| (2..=5).contains(&tree.indel_length())));
+		assert!(trees.len() < QTree::from_pairs(&pairs[..1], IndelRange::ANY).unwrap().len());
+		assert!(quartets(range) < all);
+	}
+
+	#[test]
+	fn gaps_on_both_strands() {
+		let (forward_block, forward_matching) = find_pair("both_forward", &[false; 4]);
+		let forward = PBlock::distances(&forward_block, &forward_matching);
+
+		for reverse in [[true, false, true, false], [false, true, true, false], [true; 4]] {
+			let (block, matching) = find_pair("both", &reverse);
+			assert_eq!(PBlock::distances(&block, &matching), forward);
+			assert_eq!(PBlock::gap_classes(&block, &matching), vec![vec![2, 3], vec![0, 1]]);
+
+			// The matching words are on the same strand as the words of the block and refer to the
+			// same bases as in the forward case
+			for i in 0..4 {
+				assert_eq!(matching[i].rev_comp, reverse[i]);
+				assert_eq!(matching[i].strand_position(if i < 2 { 303 } else { 300 }), forward_matching[i].position);
+			}
+		}
+	}
+}
@@ -1,5 +1,5 @@
 use std::fmt;
-use crate::PBlock;
+use crate::{PBlock, IndelRange, GapsError};
 
 #[derive(Debug, Clone)]
 pub struct QTree {
@@ -9,30 +9,30 @@ pub struct QTree {
 }
 
 impl QTree {
-	pub fn new(p1: &PBlock, p2: &PBlock) -> Option<QTree> {
+	/// Returns the quartet tree that the pair of blocks supports, or None if it doesn't support one or
+	/// its indel length is outside of the range
+	pub fn new(p1: &PBlock, p2: &PBlock, indels: IndelRange) -> Result<Option<QTree>, GapsError> {
 		if p1.len() != 4 || p2.len() != 4 {
-			return None;
+			return Ok(None);
 		}
 		
-		// panic, wenn die PBlocks verschiedene Sequenzen enthalten
-		if p1.get_sequence_names() != p2.get_sequence_names() {
-			panic!("Species name not found in P Block");
+		// Fehler, wenn die PBlocks verschiedene Sequenzen enthalten
+		let names2 = p2.get_sequence_names();
+		if let Some(name) = p1.get_sequence_names().into_iter().find(|name| !names2.contains(name)) {
+			return Err(GapsError::MissingSequence { name: name.to_string(), location: String::from("P Block") });
 		}
 
 		// Abstände
-		let g = [p1[0].gap_size(&p2[0]), p1[1].gap_size(&p2[1]), p1[2].gap_size(&p2[2]), p1[3].gap_size(&p2[3])];
+		let g = PBlock::distances(p1, p2);
 
 		// Baum konstruieren, wenn möglich
 		let pair1;
 		let pair2;
 
 		// Kein Baum möglich, wenn drei oder vier Distanzen gleich sind
-		if 	g[0] == g[1] && g[1] == g[2] ||
-			g[0] == g[1] && g[1] == g[3] ||
-			g[0] == g[2] && g[2] == g[3] ||
-			g[1] == g[2] && g[2] == g[3] {
-				return None;	
-			}
+		if g.iter().any(|d| g.iter().filter(|x| *x == d).count() >= 3) {
+			return Ok(None);
+		}
 
 		// Finde passende Distanzen, oder None, wenn alle vier verschieden sind
 		if 		g[0] == g[1] 	{ pair1 = (0, 1); pair2 = (2, 3); }
@@ -41,15 +41,19 @@ impl QTree {
 		else if g[1] == g[2] 	{ pair1 = (1, 2); pair2 = (0, 3); }
 		else if g[1] == g[3] 	{ pair1 = (1, 3); pair2 = (0, 2); }
 		else if g[2] == g[3] 	{ pair1 = (2, 3); pair2 = (0, 1); }
-		else 					{ return None; }
+		else 					{ return Ok(None); }
 
-		Some(
+		if !indels.accepts(p1, p2) {
+			return Ok(None);
+		}
+
+		Ok(Some(
 			QTree {
 				blocks: (p1.clone(), p2.clone()),
 				pair1,
 				pair2,
 			}
-		)
+		))
 	}
 
 	pub fn seq_names(&self) -> Vec<&str> {
@@ -57,20 +61,27 @@ impl QTree {
 	}
 
 	pub fn gap_sizes(&self) -> Vec<i64> {
-		vec![
-			self.blocks.1[0].position as i64 - self.blocks.0[0].position as i64,
-			self.blocks.1[1].position as i64 - self.blocks.0[1].position as i64,
-			self.blocks.1[2].position as i64 - self.blocks.0[2].position as i64,
-			self.blocks.1[3].position as i64 - self.blocks.0[3].position as i64
-		]
+		PBlock::distances(&self.blocks.0, &self.blocks.1)
 	}
 
-	pub fn from_pairs(pairs: &[(PBlock, PBlock)]) -> Vec<QTree> {
-		pairs.iter()
-			.map(|(b1, b2)| QTree::new(b1, b2))
-			.filter(|tree| tree.is_some())
-			.map(|tree| tree.unwrap())
-			.collect()
+	// Difference between the gap size of pair1 (which is always a pair of equal gap sizes) and the
+	// closest gap size of the other two sequences (see PBlock::indel_length)
+	pub fn indel_length(&self) -> i64 {
+		PBlock::indel_length(&self.blocks.0, &self.blocks.1).unwrap_or(0)
+	}
+
+	// Pairs of blocks with more than four sequences are decomposed into all quartets
+	// (the indel range is applied to each quartet)
+	pub fn from_pairs(pairs: &[(PBlock, PBlock)], indels: IndelRange) -> Result<Vec<QTree>, GapsError> {
+		let mut result = Vec::new();
+		for pair in pairs {
+			for (b1, b2) in PBlock::quartet_pairs(pair) {
+				if let Some(tree) = QTree::new(&b1, &b2, indels)? {
+					result.push(tree);
+				}
+			}
+		}
+		Ok(result)
 	}
 }
 
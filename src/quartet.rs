@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::{PBlock, QTree, GapsError};
+
+// Weighting schemes for aggregated quartets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weighting {
+	// number of supporting pairs
+	Count,
+	// strong pairs count 1, weak pairs 0.5
+	Class,
+	// sum of the indel lengths of the supporting pairs
+	Gap,
+}
+
+/// Weighted quartet topology ab|cd, where taxa = [a, b, c, d]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quartet {
+	pub taxa: [String; 4],
+	pub weight: f64,
+}
+
+impl Quartet {
+	pub fn new(taxa: [&str; 4], weight: f64) -> Quartet {
+		let mut pair1 = [taxa[0], taxa[1]];
+		let mut pair2 = [taxa[2], taxa[3]];
+		pair1.sort_unstable();
+		pair2.sort_unstable();
+		if pair2 < pair1 {
+			std::mem::swap(&mut pair1, &mut pair2);
+		}
+
+		Quartet {
+			taxa: [pair1[0], pair1[1], pair2[0], pair2[1]].map(String::from),
+			weight
+		}
+	}
+
+	pub fn from_qtree(tree: &QTree, weight: f64) -> Quartet {
+		let names = tree.seq_names();
+		Quartet::new([names[tree.pair1.0], names[tree.pair1.1], names[tree.pair2.0], names[tree.pair2.1]], weight)
+	}
+
+	/// Merges identical topologies into one quartet whose weight is determined by the weighting scheme
+	pub fn aggregate(qtrees: &[QTree], weighting: Weighting) -> Vec<Quartet> {
+		let mut weights: BTreeMap<[String; 4], f64> = BTreeMap::new();
+
+		for tree in qtrees {
+			let weight = match weighting {
+				Weighting::Count => 1.0,
+				Weighting::Class => if PBlock::strong_pair(&tree.blocks.0, &tree.blocks.1) { 1.0 } else { 0.5 },
+				Weighting::Gap => tree.indel_length() as f64,
+			};
+			*weights.entry(Quartet::from_qtree(tree, 0.0).taxa).or_insert(0.0) += weight;
+		}
+
+		weights.into_iter()
+			.map(|(taxa, weight)| Quartet { taxa, weight })
+			.collect()
+	}
+
+	/// Parses a quartet either in Newick notation ("((a,b),(c,d));", weight 1) or in
+	/// max-cut notation ("a,b|c,d" or "a,b|c,d:weight")
+	pub fn parse(line: &str) -> Result<Quartet, GapsError> {
+		let line = line.trim().trim_end_matches(';');
+
+		let (topology, weight) = if line.contains('|') {
+			match line.rsplit_once(':') {
+				Some((topology, weight)) => (topology, weight.trim().parse::<f64>().map_err(|e| GapsError::Invalid(format!("Invalid weight in \"{}\": {}", line, e)))?),
+				None => (line, 1.0)
+			}
+		}
+		else {
+			(line, 1.0)
+		};
+
+		let names = topology.split(|c| c == ',' || c == '|')
+			.map(|name| name.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace()))
+			.collect::<Vec<_>>();
+
+		if names.len() != 4 || names.iter().any(|name| name.is_empty()) {
+			return Err(GapsError::Invalid(format!("Invalid quartet \"{}\"", line)));
+		}
+
+		Ok(Quartet::new([names[0], names[1], names[2], names[3]], weight))
+	}
+}
+
+impl fmt::Display for Quartet {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{},{}|{},{}:{}", self.taxa[0], self.taxa[1], self.taxa[2], self.taxa[3], self.weight)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::IndelRange;
+
+	fn qtree(gaps: &[i64]) -> QTree {
+		let (a, b) = PBlock::pair_with_gaps(&["a", "b", "c", "d"], 0, gaps);
+		QTree::new(&a, &b, IndelRange::ANY).unwrap().unwrap()
+	}
+
+	#[test]
+	fn both_notations_are_parsed() {
+		let expected = Quartet::new(["a", "b", "c", "d"], 1.0);
+		assert_eq!(Quartet::parse("((b,a),(d,c));").unwrap(), expected);
+		assert_eq!(Quartet::parse(" ((a, b), (c, d))").unwrap(), expected);
+		assert_eq!(Quartet::parse("c,d|a,b").unwrap(), expected);
+		assert_eq!(Quartet::parse("a,b|c,d:2.5").unwrap(), Quartet::new(["a", "b", "c", "d"], 2.5));
+
+		let quartet = Quartet::new(["x", "y", "a", "b"], 0.5);
+		assert_eq!(Quartet::parse(&quartet.to_string()).unwrap(), quartet);
+	}
+
+	#[test]
+	fn malformed_quartets_are_rejected() {
+		for line in ["", "((a,b),c);", "((a,b),(c,d,e));", "a,b|c,", "a,b|c,d:x", "a,b|c,d:"] {
+			assert!(matches!(Quartet::parse(line), Err(GapsError::Invalid(_))), "{:?}", line);
+		}
+	}
+
+	#[test]
+	fn quartets_are_weighted_by_scheme() {
+		let qtrees = [
+			// strong, indel length 3
+			qtree(&[10, 10, 13, 13]),
+			// weak, indel length 2
+			qtree(&[10, 10, 12, 15]),
+			// strong, indel length 4 and another topology
+			qtree(&[10, 14, 10, 14])
+		];
+		let weights = |weighting| Quartet::aggregate(&qtrees, weighting).into_iter()
+			.map(|q| (q.taxa.join(""), q.weight))
+			.collect::<Vec<_>>();
+
+		let w = |ab, ac| vec![(String::from("abcd"), ab), (String::from("acbd"), ac)];
+		assert_eq!(weights(Weighting::Count), w(2.0, 1.0));
+		assert_eq!(weights(Weighting::Class), w(1.5, 1.0));
+		assert_eq!(weights(Weighting::Gap), w(5.0, 4.0));
+	}
+}
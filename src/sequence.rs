@@ -1,58 +1,229 @@
-use crate::SpacedWord;
+use crate::{GapsError, Pattern};
 use std::collections::HashMap;
-use needletail::sequence::Sequence as NTSequence;
+use std::sync::Arc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sequence {
-	pub name: String,
-	pub sequence: String,
-	pub seq_rev: String,
-	pub is_rev_comp: bool
+	// shared with all spaced words of this sequence
+	pub name: Arc<str>,
+	// 2-bit codes (A=0, C=1, G=2, T=3) of the forward strand, four bases per byte
+	bases: Vec<u8>,
+	// sorted, non-overlapping ranges of positions with symbols other than A/C/G/T (e.g. N); their
+	// code in bases is 0
+	ambiguous: Vec<(usize, usize)>,
+	// sorted, non-overlapping ranges of lower case (soft-masked) positions
+	lowercase: Vec<(usize, usize)>,
+	// sorted, non-overlapping ranges of positions that are excluded from spaced words (see
+	// Sequence::mask_lowercase and Sequence::mask_low_complexity)
+	masked: Vec<(usize, usize)>,
+	// union of the ambiguous and masked ranges
+	excluded: Vec<(usize, usize)>,
+	len: usize,
 }
 
 impl Sequence {
-	pub fn read_fasta_file(filename: &str) -> HashMap<String, Sequence> {
+	pub fn read_fasta_file(filename: &str) -> Result<HashMap<String, Sequence>, GapsError> {
 		let mut result = HashMap::new();
+		let mut error = None;
 
 		needletail::parse_sequence_path(
 			filename,
 			|_| {},
 			|seq| {
-				let seq_rev = String::from_utf8(seq.reverse_complement()).unwrap();
-				let header = String::from_utf8(seq.id.into_owned()).unwrap();
-				let sequence = String::from_utf8(seq.seq.into_owned()).unwrap();
+				if error.is_some() {
+					return;
+				}
 
-				result.insert(header.clone(), Sequence { name: header, sequence, seq_rev, is_rev_comp: false });
+				match String::from_utf8(seq.id.into_owned()) {
+					Ok(header) => {
+						let sequence = Sequence::new(&header, &seq.seq);
+						result.insert(header, sequence);
+					},
+					Err(_) => error = Some(GapsError::parse(filename, None, &format!("Invalid UTF-8 in record {}", result.len() + 1)))
+				}
 			},
 		)
-			.expect("parsing failed");
+			.map_err(|e| GapsError::parse(filename, None, &e.to_string()))?;
 
-		result
+		match error {
+			Some(e) => Err(e),
+			None => Ok(result)
+		}
+	}
+
+	pub fn new(name: &str, seq: &[u8]) -> Sequence {
+		let mut bases = vec![0; (seq.len() + 3) / 4];
+		let mut ambiguous: Vec<(usize, usize)> = Vec::new();
+		let mut lowercase: Vec<(usize, usize)> = Vec::new();
+
+		for (i, base) in seq.iter().enumerate() {
+			if base.is_ascii_lowercase() {
+				add_position(&mut lowercase, i);
+			}
+
+			let code = match base_code(*base) {
+				Some(code) => code as u8,
+				None => {
+					add_position(&mut ambiguous, i);
+					continue;
+				}
+			};
+			bases[i / 4] |= code << (2 * (i % 4));
+		}
+
+		Sequence {
+			name: Arc::from(name),
+			bases,
+			ambiguous,
+			lowercase,
+			masked: Vec::new(),
+			excluded: Vec::new(),
+			len: seq.len(),
+		}
 	}
 
 	pub fn len(&self) -> usize {
-		self.sequence.len()
+		self.len
 	}
 
 	pub fn is_empty(&self) -> bool {
 		self.len() == 0
 	}
 
-	pub fn get_rev_comp(&self) -> Sequence {
-		Sequence {
-			name: self.name.clone(),
-			sequence: self.seq_rev.clone(),
-			seq_rev: self.sequence.clone(),
-			is_rev_comp: !self.is_rev_comp
+	/// Number of masked positions
+	pub fn masked_len(&self) -> usize {
+		self.masked.iter().map(|range| range.1 - range.0).sum()
+	}
+
+	/// Excludes lower case (soft-masked) positions from spaced words
+	pub fn mask_lowercase(&mut self) {
+		self.masked = merge_ranges(&self.masked, &self.lowercase);
+		self.excluded = merge_ranges(&self.ambiguous, &self.masked);
+	}
+
+	/// Excludes low-complexity regions from spaced words, as found by the DUST algorithm: every window
+	/// of the given length (windows overlap by half their length) whose triplet score exceeds the
+	/// threshold is masked. The score is ten times the sum of c_t * (c_t - 1) / 2 over the counts c_t
+	/// of all triplets t of the window, divided by the number of triplets minus one (as in the original
+	/// DUST, whose default threshold is 20).
+	pub fn mask_low_complexity(&mut self, window: usize, threshold: f64) {
+		if window < 4 || self.len < window {
+			return;
+		}
+
+		let mut low_complexity: Vec<(usize, usize)> = Vec::new();
+		let step = window / 2;
+		let mut start = 0;
+		loop {
+			// Windows at the end of the sequence are moved back to fit into it
+			let start_window = start.min(self.len - window);
+			// Only the codes of the current window are decoded, to keep the memory independent of
+			// the length of the sequence
+			let codes = self.codes(start_window, start_window + window, false);
+			let mut counts = [0u32; 64];
+			let mut triplets = 0;
+			for triplet in codes.windows(3) {
+				if let [Some(a), Some(b), Some(c)] = triplet {
+					counts[(a * 16 + b * 4 + c) as usize] += 1;
+					triplets += 1;
+				}
+			}
+
+			let score = 10.0 * counts.iter().map(|&c| (c * c.saturating_sub(1) / 2) as f64).sum::<f64>() / (triplets.max(2) - 1) as f64;
+			if score > threshold {
+				match low_complexity.last_mut() {
+					Some(range) if range.1 >= start_window => range.1 = start_window + window,
+					_ => low_complexity.push((start_window, start_window + window))
+				}
+			}
+
+			if start_window + window >= self.len {
+				break;
+			}
+			start += step;
 		}
+
+		self.masked = merge_ranges(&self.masked, &low_complexity);
+		self.excluded = merge_ranges(&self.ambiguous, &self.masked);
+	}
+
+	// Returns the 2-bit codes of the positions start..end of this sequence (or of its reverse
+	// complement), None for symbols other than A/C/G/T
+	fn codes(&self, start: usize, end: usize, reverse: bool) -> Vec<Option<u64>> {
+		self.codes_excluding(start, end, reverse, &self.ambiguous)
+	}
+
+	// Like codes(), but None for all positions in the given ranges (which include the ambiguous ones)
+	fn codes_excluding(&self, start: usize, end: usize, reverse: bool, excluded: &[(usize, usize)]) -> Vec<Option<u64>> {
+		let (start, end) = if reverse { (self.len - end, self.len - start) } else { (start, end) };
+
+		let mut range = excluded.partition_point(|range| range.1 <= start);
+		let mut result = Vec::with_capacity(end - start);
+		for i in start..end {
+			while range < excluded.len() && excluded[range].1 <= i {
+				range += 1;
+			}
+
+			if range < excluded.len() && excluded[range].0 <= i {
+				result.push(None);
+			}
+			else {
+				result.push(Some(((self.bases[i / 4] >> (2 * (i % 4))) & 3) as u64));
+			}
+		}
+
+		if reverse {
+			// The complement of a code c is 3 - c
+			result.reverse();
+			for code in result.iter_mut().flatten() {
+				*code = 3 - *code;
+			}
+		}
+
+		result
+	}
+
+	/// Checks whether the word occurs at the given forward strand position (see SpacedWord::position)
+	/// on the forward or reverse strand. Only the match positions of the pattern are compared, if one
+	/// is given. Symbols other than A/C/G/T only match each other.
+	pub fn matches_word(&self, word: &str, position: usize, reverse: bool, pattern: Option<&Pattern>) -> bool {
+		if position + word.len() > self.len {
+			return false;
+		}
+		if let Some(pattern) = pattern {
+			if pattern.len() != word.len() {
+				return false;
+			}
+		}
+
+		let start = if reverse { self.len - position - word.len() } else { position };
+		let codes = self.codes(start, start + word.len(), reverse);
+		word.bytes().zip(codes).enumerate().all(|(i, (base, code))| {
+			pattern.map_or(false, |pattern| !pattern.is_match(i)) || base_code(base) == code
+		})
+	}
+
+	/// Returns the 2-bit codes of the word of length len at the given forward strand position (see
+	/// SpacedWord::position) in the order of the strand, None for symbols other than A/C/G/T
+	pub fn word_codes(&self, position: usize, len: usize, reverse: bool) -> Vec<Option<u64>> {
+		let start = if reverse { self.len - position - len } else { position };
+		self.codes(start, start + len, reverse)
 	}
 
-	pub fn spaced_words(&self, pattern: &str, mut min_pos: i64, mut max_pos: i64, reverse: bool) -> Vec<SpacedWord> {
+	/// Returns the encoded match positions and the positions of all spaced words in the window
+	/// min_pos..max_pos that contain only A/C/G/T on match positions (and no masked positions). The
+	/// window refers to the strand that is read (the reverse complement, if reverse is set), the
+	/// returned positions are always the forward strand positions of the first base (see
+	/// SpacedWord::position).
+	///
+	/// The key of a word with the codes c_1..c_m on its match positions (A=0, C=1, G=2, T=3) is
+	/// (((c_1 << 2) + c_2) << 2 ... + c_m) << 2, which is unique for patterns of up to MAX_WEIGHT match
+	/// positions (see pattern::MAX_WEIGHT).
+	pub fn spaced_words(&self, pattern: &Pattern, mut min_pos: i64, mut max_pos: i64, reverse: bool) -> Result<Vec<(u128, i64)>, GapsError> {
 		if min_pos > max_pos {
-			panic!("min_pos > max_pos...sollte nicht passieren");
+			return Err(GapsError::Invalid(format!("Invalid window {}..{} in sequence {}", min_pos, max_pos, self.name)));
 		}
 
-		let mut result = Vec::with_capacity((max_pos - min_pos) as usize);
 		let self_len = self.len() as i64;
 		let pat_len = pattern.len() as i64;
 
@@ -64,24 +235,198 @@ impl Sequence {
 		}
 
 		if pat_len > max_pos - min_pos {
-			return result;
+			return Ok(Vec::new());
 		}
 
-		let seq = if reverse { &self.seq_rev } else { &self.sequence };
+		let runs = pattern.runs();
+
+		// For every run length k, the k-mer that starts at each position of the window is encoded with
+		// a rolling 2-bit encoding (None, if it contains an ambiguous base)
+		let codes = if self.masked.is_empty() {
+			self.codes(min_pos as usize, max_pos as usize, reverse)
+		}
+		else {
+			self.codes_excluding(min_pos as usize, max_pos as usize, reverse, &self.excluded)
+		};
+		let mut kmers: HashMap<usize, Vec<Option<u128>>> = HashMap::new();
+		for &(_, k) in &runs {
+			kmers.entry(k).or_insert_with(|| {
+				let mut result = Vec::with_capacity(codes.len());
+				let mut value = 0u128;
+				let mut last_ambiguous = None;
+				for (i, code) in codes.iter().enumerate() {
+					value = shift(value, 2) | code.unwrap_or(0) as u128;
+					if code.is_none() {
+						last_ambiguous = Some(i);
+					}
+					if i + 1 >= k {
+						let start = i + 1 - k;
+						result.push(match last_ambiguous {
+							Some(a) if a >= start => None,
+							_ => Some(value)
+						});
+					}
+				}
+				result
+			});
+		}
 
-		for i in min_pos..max_pos - pat_len {
-			if let Some(word) = SpacedWord::new(
-					&self.name,
-					i as i64,
-					&Some(&seq[(i as usize)..(i+pat_len) as usize]),
-					&Some(pattern),
-					self.is_rev_comp
-				) {
-				result.push(word);
+		// The key is the concatenation of the k-mers of all runs
+		let mut result = Vec::with_capacity((max_pos - min_pos) as usize);
+		'words: for i in 0..(max_pos - min_pos - pat_len) as usize {
+			let mut key = 0;
+			for (offset, k) in &runs {
+				match kmers[k][i + offset] {
+					Some(kmer) => key = shift(key, 2 * k) | (kmer & mask(2 * k)),
+					None => continue 'words
+				}
 			}
+			let position = if reverse { self_len - (min_pos + i as i64) - pat_len } else { min_pos + i as i64 };
+			result.push((shift(key, 2), position));
+		}
+
+		Ok(result)
+	}
+}
 
+// Adds position i to sorted, non-overlapping ranges that end at or before i
+fn add_position(ranges: &mut Vec<(usize, usize)>, i: usize) {
+	match ranges.last_mut() {
+		Some(range) if range.1 == i => range.1 += 1,
+		_ => ranges.push((i, i + 1))
+	}
+}
+
+// Union of two lists of sorted, non-overlapping ranges
+fn merge_ranges(a: &[(usize, usize)], b: &[(usize, usize)]) -> Vec<(usize, usize)> {
+	let mut all = a.iter().chain(b).copied().collect::<Vec<_>>();
+	all.sort_unstable();
+
+	let mut result: Vec<(usize, usize)> = Vec::with_capacity(all.len());
+	for (start, end) in all {
+		match result.last_mut() {
+			Some(range) if range.1 >= start => range.1 = range.1.max(end),
+			_ => result.push((start, end))
 		}
+	}
+	result
+}
 
-		result
+/// 2-bit code of a base (A=0, C=1, G=2, T=3, ignoring case), None for other symbols
+pub(crate) fn base_code(base: u8) -> Option<u64> {
+	match base {
+		b'A' | b'a' => Some(0),
+		b'C' | b'c' => Some(1),
+		b'G' | b'g' => Some(2),
+		b'T' | b't' => Some(3),
+		_ => None
+	}
+}
+
+// Left shift that drops all bits if the shift is larger than the number of bits
+fn shift(value: u128, bits: usize) -> u128 {
+	if bits >= 128 { 0 } else { value << bits }
+}
+
+// The lowest bits bits
+fn mask(bits: usize) -> u128 {
+	if bits >= 128 { u128::MAX } else { (1 << bits) - 1 }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rev_comp(seq: &str) -> String {
+		seq.chars().rev()
+			.map(|c| match c.to_ascii_uppercase() { 'A' => 'T', 'C' => 'G', 'G' => 'C', 'T' => 'A', c => c })
+			.collect()
+	}
+
+	#[test]
+	fn reverse_strand_words_have_forward_positions() {
+		let forward = "ACGTTGCANNGTACCAGTTACGATCGGATCCATGACgtacTTAGCA";
+		let len = forward.len() as i64;
+		let seq = Sequence::new("s", forward.as_bytes());
+		let rc = Sequence::new("rc", rev_comp(forward).as_bytes());
+
+		for pattern in ["11111", "1101011", "1"] {
+			let pattern = pattern.parse::<Pattern>().unwrap();
+			let pat_len = pattern.len() as i64;
+			let reverse = seq.spaced_words(&pattern, 3, 40, true).unwrap();
+			let expected = rc.spaced_words(&pattern, 3, 40, false).unwrap().into_iter()
+				.map(|(key, position)| (key, len - position - pat_len))
+				.collect::<Vec<_>>();
+			assert_eq!(reverse, expected);
+		}
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn ambiguous_bases_are_skipped() {
+		let seq = Sequence::new("s", b"ACGTNACGTA");
+		let positions = seq.spaced_words(&"111".parse().unwrap(), 0, 10, false).unwrap().into_iter()
+			.map(|(_, position)| position)
+			.collect::<Vec<_>>();
+		assert_eq!(positions, vec![0, 1, 5, 6]);
+
+		// On the reverse strand, the window starts at the end of the forward strand
+		let positions = seq.spaced_words(&"111".parse().unwrap(), 0, 10, true).unwrap().into_iter()
+			.map(|(_, position)| position)
+			.collect::<Vec<_>>();
+		assert_eq!(positions, vec![7, 6, 5, 1]);
+	}
+
+	#[test]
+	fn keys_encode_match_positions() {
+		let seq = Sequence::new("s", b"ACGTACGT");
+		// Match positions of "1011" at position 0 are A, G, T: ((0 << 2) + 2 << 2) + 3 << 2 = 44
+		assert_eq!(seq.spaced_words(&"1011".parse().unwrap(), 0, 8, false).unwrap()[0], (44, 0));
+	}
+
+	#[test]
+	fn inverted_window_is_an_error() {
+		let seq = Sequence::new("s", b"ACGTACGT");
+		assert!(matches!(seq.spaced_words(&"11".parse().unwrap(), 6, 2, false), Err(GapsError::Invalid(_))));
+	}
+
+	#[test]
+	fn masked_positions_are_excluded() {
+		let mut seq = Sequence::new("s", b"ACGTacgtACGT");
+		let pattern = "111".parse::<Pattern>().unwrap();
+		assert_eq!(seq.spaced_words(&pattern, 0, 12, false).unwrap().len(), 9);
+
+		seq.mask_lowercase();
+		assert_eq!(seq.masked_len(), 4);
+		let positions = seq.spaced_words(&pattern, 0, 12, false).unwrap().into_iter()
+			.map(|(_, position)| position)
+			.collect::<Vec<_>>();
+		assert_eq!(positions, vec![0, 1, 8]);
+	}
+
+	#[test]
+	fn low_complexity_regions_are_masked() {
+		let random = "GATTCGCAGTACCGTAAGCTTGCAACGGTTACAGCGTATGCCATGAGTCCTAGAACTGCATCGGAT";
+		let repeat = "CA".repeat(48);
+		let mut seq = Sequence::new("s", format!("{}{}{}", random, repeat, random).as_bytes());
+		seq.mask_low_complexity(64, 20.0);
+
+		// Only the windows that overlap the repeat are masked
+		assert!(seq.masked_len() >= repeat.len());
+		assert!(seq.masked.iter().all(|range| range.0 >= random.len() - 64 && range.1 <= random.len() + repeat.len() + 64));
+
+		let mut seq = Sequence::new("s", random.repeat(2).as_bytes());
+		seq.mask_low_complexity(64, 20.0);
+		assert_eq!(seq.masked_len(), 0);
+	}
+
+	#[test]
+	fn heavy_patterns_keep_all_match_positions() {
+		// Words of weight 40 that only differ in their first base
+		let seq = Sequence::new("s", format!("A{}C{}", "ACGT".repeat(10), "ACGT".repeat(10)).as_bytes());
+		let words = seq.spaced_words(&"1".repeat(40).parse().unwrap(), 0, 82, false).unwrap();
+		assert_eq!(words[0].1, 0);
+		assert_eq!(words[41].1, 41);
+		assert_ne!(words[0].0, words[41].0);
+		assert_eq!(words[0].0 & 0xff, words[41].0 & 0xff);
+	}
+}
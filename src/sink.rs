@@ -0,0 +1,584 @@
+use crate::{PBlock, IndelRange, QTree, Quartet, Weighting, GapsError, tools};
+use crate::output::{self, Coding, SYMBOLS, MISSING, MAX_STATES_PAUP, MAX_STATES_PHYLIP};
+use crate::opt::Gaps;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Output that receives the pairs in chunks. Formats that depend on all pairs (e.g. a matrix with
+/// one row per taxon) keep only aggregated data or temporary files in between; the output file is
+/// complete after finish() has been called.
+pub trait PairSink {
+	fn write_pairs(&mut self, pairs: &[(PBlock, PBlock)]) -> Result<(), GapsError>;
+
+	/// Returns the number of characters with states that couldn't be represented (see MatrixSink)
+	fn finish(&mut self) -> Result<usize, GapsError>;
+}
+
+/// Creates the sink for the output format of the options
+pub fn create(opt: &Gaps) -> Result<Box<dyn PairSink>, GapsError> {
+	let indels = IndelRange { min: opt.min_indel, max: opt.max_indel };
+	Ok(match &opt.format[..] {
+		"max-cut" => match &opt.weights[..] {
+			"count" => Box::new(QuartetSink::new(&opt.outfile, Weighting::Count, indels)),
+			"class" => Box::new(QuartetSink::new(&opt.outfile, Weighting::Class, indels)),
+			"gap" => Box::new(QuartetSink::new(&opt.outfile, Weighting::Gap, indels)),
+			_ => Box::new(QTreeSink::new(&opt.outfile, indels)?)
+		},
+		"phylip" => Box::new(MatrixSink::new(&opt.outfile, MatrixFormat::Phylip)?),
+		"paup" => {
+			let coding = match &opt.coding[..] {
+				"ordered" => Coding::Ordered,
+				"stepmatrix" => Coding::StepMatrix,
+				_ => Coding::Unordered
+			};
+			Box::new(MatrixSink::new(&opt.outfile, MatrixFormat::Paup(coding))?)
+		},
+		"splits" => Box::new(SplitsSink::new(&opt.outfile)),
+		format => return Err(GapsError::Invalid(format!("Invalid output format \"{}\"", format)))
+	})
+}
+
+fn create_file(filename: &str) -> Result<BufWriter<File>, GapsError> {
+	File::create(filename)
+		.map(BufWriter::new)
+		.map_err(|e| GapsError::io(filename, e))
+}
+
+// === QTreeSink ===================================================================================
+
+/// Writes the quartet trees of the pairs in Newick notation (one per line)
+pub struct QTreeSink {
+	filename: String,
+	f: BufWriter<File>,
+	indels: IndelRange,
+}
+
+impl QTreeSink {
+	pub fn new(filename: &str, indels: IndelRange) -> Result<QTreeSink, GapsError> {
+		Ok(QTreeSink { filename: filename.to_string(), f: create_file(filename)?, indels })
+	}
+}
+
+impl PairSink for QTreeSink {
+	fn write_pairs(&mut self, pairs: &[(PBlock, PBlock)]) -> Result<(), GapsError> {
+		for tree in QTree::from_pairs(pairs, self.indels)? {
+			writeln!(self.f, "{}", tree).map_err(|e| GapsError::io(&self.filename, e))?;
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<usize, GapsError> {
+		self.f.flush().map_err(|e| GapsError::io(&self.filename, e))?;
+		Ok(0)
+	}
+}
+
+// === QuartetSink =================================================================================
+
+/// Merges identical quartet topologies (see Quartet::aggregate); only the distinct quartets are kept
+pub struct QuartetSink {
+	filename: String,
+	weighting: Weighting,
+	indels: IndelRange,
+	weights: BTreeMap<[String; 4], f64>,
+}
+
+impl QuartetSink {
+	pub fn new(filename: &str, weighting: Weighting, indels: IndelRange) -> QuartetSink {
+		QuartetSink { filename: filename.to_string(), weighting, indels, weights: BTreeMap::new() }
+	}
+}
+
+impl PairSink for QuartetSink {
+	fn write_pairs(&mut self, pairs: &[(PBlock, PBlock)]) -> Result<(), GapsError> {
+		for quartet in Quartet::aggregate(&QTree::from_pairs(pairs, self.indels)?, self.weighting) {
+			*self.weights.entry(quartet.taxa).or_insert(0.0) += quartet.weight;
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<usize, GapsError> {
+		let quartets = std::mem::take(&mut self.weights).into_iter()
+			.map(|(taxa, weight)| Quartet { taxa, weight })
+			.collect::<Vec<Quartet>>();
+		output::to_weighted_quartets(&quartets, &self.filename)?;
+		Ok(0)
+	}
+}
+
+// === SplitsSink ==================================================================================
+
+/// Writes the splits of all pairs as NEXUS taxa and splits blocks (e.g. for SplitsTree). A split of
+/// a block that doesn't contain all taxa says nothing about the missing taxa, so only the pairs of
+/// blocks with all taxa are used. Identical splits are merged, their weight is the number of
+/// supporting pairs.
+pub struct SplitsSink {
+	filename: String,
+	species: BTreeSet<String>,
+	// number of pairs that separate each group of taxa, by the taxa of the blocks
+	groups: BTreeMap<(Vec<String>, Vec<String>), usize>,
+}
+
+impl SplitsSink {
+	pub fn new(filename: &str) -> SplitsSink {
+		SplitsSink { filename: filename.to_string(), species: BTreeSet::new(), groups: BTreeMap::new() }
+	}
+}
+
+impl PairSink for SplitsSink {
+	fn write_pairs(&mut self, pairs: &[(PBlock, PBlock)]) -> Result<(), GapsError> {
+		for (a, b) in pairs {
+			let names = a.get_sequence_names().into_iter().map(String::from).collect::<Vec<String>>();
+			self.species.extend(names.iter().cloned());
+			// Both groups of a pair can imply the same split, which is counted once
+			let groups = PBlock::splits(a, b).into_iter()
+				.map(|(group, other)| if group.contains(&&names[0][..]) { other } else { group })
+				.map(|mut group| { group.sort_unstable(); group.into_iter().map(String::from).collect() })
+				.collect::<BTreeSet<Vec<String>>>();
+			for group in groups {
+				*self.groups.entry((names.clone(), group)).or_insert(0) += 1;
+			}
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<usize, GapsError> {
+		let species = self.species.iter().collect::<Vec<&String>>();
+
+		// Each split is represented by the side that doesn't contain the first taxon
+		let mut splits: BTreeMap<Vec<usize>, usize> = BTreeMap::new();
+		for ((names, group), count) in &self.groups {
+			if names.len() < species.len() {
+				continue;
+			}
+
+			let group = group.iter()
+				.map(|name| species.binary_search(&name).unwrap())
+				.collect::<Vec<usize>>();
+			let side = if group.contains(&0) {
+				(0..species.len()).filter(|i| !group.contains(i)).collect()
+			}
+			else {
+				group
+			};
+			*splits.entry(side).or_insert(0) += count;
+		}
+
+		let mut content = format!("#NEXUS\n\n\
+						BEGIN Taxa;\n\
+						DIMENSIONS ntax={};\n\
+						TAXLABELS\n", species.len());
+		for (i, name) in species.iter().enumerate() {
+			content = format!("{}[{}] {}\n", content, i + 1, output::quote_label(name));
+		}
+		content = format!("{};\n\
+						END; [Taxa]\n\n\
+						BEGIN Splits;\n\
+						DIMENSIONS ntax={} nsplits={};\n\
+						FORMAT labels=no weights=yes confidences=no intervals=no;\n\
+						MATRIX\n", content, species.len(), splits.len());
+		for (i, (side, weight)) in splits.iter().enumerate() {
+			let side = side.iter().map(|t| (t + 1).to_string()).collect::<Vec<_>>();
+			content = format!("{}[{}, size={}]\t{}\t{},\n", content, i + 1, side.len(), weight, side.join(" "));
+		}
+		content = format!("{};\nEND; [Splits]\n", content);
+
+		let mut f = File::create(&self.filename).map_err(|e| GapsError::io(&self.filename, e))?;
+		f.write_all(content.as_bytes()).map_err(|e| GapsError::io(&self.filename, e))?;
+		Ok(0)
+	}
+}
+
+// === MatrixSink ==================================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatrixFormat {
+	Phylip,
+	Paup(Coding),
+}
+
+// Characters of one taxon, which are collected in a temporary file
+struct Row {
+	path: PathBuf,
+	f: BufWriter<File>,
+	// number of characters before the taxon occurred for the first time (missing information)
+	first_column: usize,
+}
+
+/// Parsimony matrix for PHYLIP pars or PAUP with one character per pair. States are assigned in order
+/// of increasing gap size. If a character has more states than the format can represent, the
+/// sequences of all further states are marked as missing information.
+///
+/// Since the taxa are only known after the last pair, the characters of each taxon are written to a
+/// temporary file and the matrix is assembled in finish().
+pub struct MatrixSink {
+	filename: String,
+	format: MatrixFormat,
+	tmp_dir: PathBuf,
+	rows: BTreeMap<String, Row>,
+	columns: usize,
+	truncated: usize,
+	// step matrix number of each distinct set of relative gap sizes and the typeset entries of all
+	// characters (only used with Coding::StepMatrix)
+	step_matrices: HashMap<Vec<i64>, usize>,
+	typeset: Option<BufWriter<File>>,
+}
+
+impl MatrixSink {
+	pub fn new(filename: &str, format: MatrixFormat) -> Result<MatrixSink, GapsError> {
+		let tmp_dir = tools::create_tmp_folder()?;
+		let typeset = if format == MatrixFormat::Paup(Coding::StepMatrix) {
+			let path = tmp_dir.join("typeset");
+			Some(create_file(&path.to_string_lossy())?)
+		}
+		else {
+			None
+		};
+
+		Ok(MatrixSink {
+			filename: filename.to_string(),
+			format,
+			tmp_dir,
+			rows: BTreeMap::new(),
+			columns: 0,
+			truncated: 0,
+			step_matrices: HashMap::new(),
+			typeset
+		})
+	}
+
+	fn max_states(&self) -> usize {
+		match self.format {
+			MatrixFormat::Phylip => MAX_STATES_PHYLIP,
+			MatrixFormat::Paup(_) => MAX_STATES_PAUP
+		}
+	}
+
+	fn tmp_error(&self, e: io::Error) -> GapsError {
+		GapsError::io(&self.tmp_dir.to_string_lossy(), e)
+	}
+
+	fn write_pair(&mut self, a: &PBlock, b: &PBlock) -> Result<(), GapsError> {
+		let max_states = self.max_states();
+		let classes = PBlock::gap_classes(a, b);
+		if classes.len() > max_states {
+			self.truncated += 1;
+		}
+
+		let mut symbols: HashMap<&str, &str> = HashMap::new();
+		for (class, symbol) in classes.iter().zip(&SYMBOLS[..max_states]) {
+			for j in class {
+				symbols.insert(&a[*j].seq_name, symbol);
+			}
+		}
+
+		for name in a.get_sequence_names() {
+			if !self.rows.contains_key(name) {
+				let path = self.tmp_dir.join(self.rows.len().to_string());
+				let f = File::create(&path).map_err(|e| self.tmp_error(e))?;
+				self.rows.insert(name.to_string(), Row { path, f: BufWriter::new(f), first_column: self.columns });
+			}
+		}
+
+		for (name, row) in self.rows.iter_mut() {
+			let symbol = symbols.get(&name[..]).cloned().unwrap_or(MISSING);
+			write!(row.f, " {}", symbol).map_err(|e| GapsError::io(&row.path.to_string_lossy(), e))?;
+		}
+
+		if let Some(typeset) = &mut self.typeset {
+			// Only relative gap sizes are relevant for the step matrix
+			let distances = PBlock::distances(a, b);
+			let dists = classes.iter()
+				.take(max_states)
+				.map(|class| distances[class[0]] - distances[classes[0][0]])
+				.collect::<Vec<i64>>();
+
+			let separator = if self.columns > 0 { ", " } else { "" };
+			let entry = if dists.len() < 2 {
+				format!("{}unord: {}", separator, self.columns + 1)
+			}
+			else {
+				let n = self.step_matrices.len() + 1;
+				let t = *self.step_matrices.entry(dists).or_insert(n);
+				format!("{}gaps{}: {}", separator, t, self.columns + 1)
+			};
+			typeset.write_all(entry.as_bytes()).map_err(|e| GapsError::io(&self.tmp_dir.to_string_lossy(), e))?;
+		}
+
+		self.columns += 1;
+		Ok(())
+	}
+
+	// Labels are padded to name_len characters
+	fn write_matrix(&mut self, f: &mut BufWriter<File>, labels: &[String], name_len: usize) -> Result<(), GapsError> {
+		let filename = &self.filename;
+
+		for (i, (label, row)) in labels.iter().zip(self.rows.values_mut()).enumerate() {
+			row.f.flush().map_err(|e| GapsError::io(&row.path.to_string_lossy(), e))?;
+
+			let separator = if i > 0 { "\n" } else { "" };
+			let padding = " ".repeat(name_len - label.chars().count());
+			write!(f, "{}{}{}{}", separator, label, padding, format!(" {}", MISSING).repeat(row.first_column))
+				.map_err(|e| GapsError::io(filename, e))?;
+
+			let mut characters = File::open(&row.path).map_err(|e| GapsError::io(&row.path.to_string_lossy(), e))?;
+			io::copy(&mut characters, f).map_err(|e| GapsError::io(filename, e))?;
+		}
+
+		Ok(())
+	}
+
+	fn write_phylip(&mut self) -> Result<(), GapsError> {
+		let species = self.rows.keys().cloned().collect::<Vec<String>>();
+		let labels = output::truncate_labels(&species, 9);
+
+		let mut f = create_file(&self.filename)?;
+		writeln!(f, "{} {}", species.len(), self.columns).map_err(|e| GapsError::io(&self.filename, e))?;
+		self.write_matrix(&mut f, &labels, 9)?;
+		f.flush().map_err(|e| GapsError::io(&self.filename, e))?;
+
+		// Because taxon names are truncated to 9 characters, a table that maps the labels to the full
+		// names is written to <filename>.taxa
+		let table_file = format!("{}.taxa", self.filename);
+		let mut f = create_file(&table_file)?;
+		for (label, name) in labels.iter().zip(&species) {
+			writeln!(f, "{}\t{}", label, name).map_err(|e| GapsError::io(&table_file, e))?;
+		}
+		f.flush().map_err(|e| GapsError::io(&table_file, e))
+	}
+
+	fn write_paup(&mut self, coding: Coding) -> Result<(), GapsError> {
+		let labels = self.rows.keys().map(|name| output::quote_label(name)).collect::<Vec<String>>();
+		let name_len = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+
+		let mut f = create_file(&self.filename)?;
+		write!(f, "#NEXUS\n\
+				begin data;\n\
+				\tdimensions ntax={} nchar={};\n\
+				\tformat datatype=standard missing={} interleave symbols=\"{}\";\n\
+				matrix\n\n", labels.len(), self.columns, MISSING, SYMBOLS[..MAX_STATES_PAUP].join(""))
+			.map_err(|e| GapsError::io(&self.filename, e))?;
+		self.write_matrix(&mut f, &labels, name_len)?;
+		write!(f, "\n;\nend;\n\n").map_err(|e| GapsError::io(&self.filename, e))?;
+
+		match coding {
+			Coding::Unordered => (),
+			Coding::Ordered => write!(f, "begin assumptions;\n\
+					\ttypeset * gaps = ord: all;\n\
+					end;\n\n").map_err(|e| GapsError::io(&self.filename, e))?,
+			Coding::StepMatrix => self.write_step_matrices(&mut f)?,
+		}
+
+		write!(f, "begin paup;\n\
+				\tset maxtrees=1000;\n\
+				\tset increase=auto;\n\
+				\tHSearch addseq=random nreps=20;\n\
+				\tSaveTrees format=newick file=pars.nwk replace=yes;\n\
+				\tquit;\n\
+				end;").map_err(|e| GapsError::io(&self.filename, e))?;
+		f.flush().map_err(|e| GapsError::io(&self.filename, e))
+	}
+
+	// Assumptions block with one step matrix per distinct set of gap sizes; the cost of a change
+	// between two states is the difference of their gap sizes
+	fn write_step_matrices(&mut self, f: &mut BufWriter<File>) -> Result<(), GapsError> {
+		let mut step_matrices = self.step_matrices.iter().collect::<Vec<_>>();
+		step_matrices.sort_unstable_by_key(|(_, t)| **t);
+
+		let mut content = String::from("begin assumptions;\n");
+		for (dists, t) in step_matrices {
+			content = format!("{}\tusertype gaps{} (stepmatrix) = {}\n\t\t{}\n", content, t, dists.len(), SYMBOLS[..dists.len()].join(" "));
+			for (i, d1) in dists.iter().enumerate() {
+				let costs = dists.iter().enumerate()
+					.map(|(j, d2)| if i == j { String::from(".") } else { (d1 - d2).abs().to_string() })
+					.collect::<Vec<_>>();
+				content = format!("{}\t\t[{}] {}\n", content, SYMBOLS[i], costs.join(" "));
+			}
+			content = format!("{}\t;\n", content);
+		}
+		write!(f, "{}\ttypeset * gaps = ", content).map_err(|e| GapsError::io(&self.filename, e))?;
+
+		let path = self.tmp_dir.join("typeset");
+		if let Some(mut typeset) = self.typeset.take() {
+			typeset.flush().map_err(|e| self.tmp_error(e))?;
+		}
+		let mut typeset = File::open(&path).map_err(|e| self.tmp_error(e))?;
+		io::copy(&mut typeset, f).map_err(|e| GapsError::io(&self.filename, e))?;
+
+		write!(f, ";\nend;\n\n").map_err(|e| GapsError::io(&self.filename, e))
+	}
+}
+
+impl PairSink for MatrixSink {
+	fn write_pairs(&mut self, pairs: &[(PBlock, PBlock)]) -> Result<(), GapsError> {
+		for (a, b) in pairs {
+			self.write_pair(a, b)?;
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<usize, GapsError> {
+		match self.format {
+			MatrixFormat::Phylip => self.write_phylip()?,
+			MatrixFormat::Paup(coding) => self.write_paup(coding)?
+		}
+		Ok(self.truncated)
+	}
+}
+
+impl Drop for MatrixSink {
+	fn drop(&mut self) {
+		self.rows.clear();
+		self.typeset = None;
+		let _ = fs::remove_dir_all(&self.tmp_dir);
+	}
+}
+
+// === PairsSink ===================================================================================
+
+/// Writes the pairs in a human-readable format (see PBlock::pair_to_string)
+pub struct PairsSink {
+	filename: String,
+	f: BufWriter<File>,
+}
+
+impl PairsSink {
+	pub fn new(filename: &str) -> Result<PairsSink, GapsError> {
+		Ok(PairsSink { filename: filename.to_string(), f: create_file(filename)? })
+	}
+}
+
+impl PairSink for PairsSink {
+	fn write_pairs(&mut self, pairs: &[(PBlock, PBlock)]) -> Result<(), GapsError> {
+		for pair in pairs {
+			self.f.write_all(PBlock::pair_to_string(pair).as_bytes()).map_err(|e| GapsError::io(&self.filename, e))?;
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> Result<usize, GapsError> {
+		self.f.flush().map_err(|e| GapsError::io(&self.filename, e))?;
+		Ok(0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pair(names: &[&str], gaps: &[i64]) -> (PBlock, PBlock) {
+		PBlock::pair_with_gaps(names, 100, gaps)
+	}
+
+	#[test]
+	fn splits_of_blocks_without_all_taxa_are_ignored() {
+		let tmp_dir = tools::create_tmp_folder().unwrap();
+		let filename = tmp_dir.join("splits").to_string_lossy().into_owned();
+
+		let mut sink = SplitsSink::new(&filename);
+		sink.write_pairs(&[
+			pair(&["A", "B", "C", "D", "E"], &[10, 10, 13, 13, 13]),
+			pair(&["A", "B", "C", "D", "E"], &[10, 10, 13, 13, 13]),
+			// Would be {C,D} | {A,B,E} if E was added to the other side
+			pair(&["A", "B", "C", "D"], &[10, 10, 13, 13])
+		]).unwrap();
+		sink.finish().unwrap();
+
+		let content = fs::read_to_string(&filename).unwrap();
+		fs::remove_dir_all(&tmp_dir).unwrap();
+		assert!(content.contains("DIMENSIONS ntax=5 nsplits=1;"));
+		assert!(content.contains("[1, size=3]\t2\t3 4 5,"));
+	}
+
+	const NAMES: [&str; 4] = ["Homo sapiens chr1", "Homo sapiens chr2", "Mus", "O'Brien"];
+
+	// Writes the pairs to a matrix and returns the content of the output file and the truncated count
+	fn write_matrix(format: MatrixFormat, pairs: &[(PBlock, PBlock)], extension: &str) -> (String, usize, Option<String>) {
+		let tmp_dir = tools::create_tmp_folder().unwrap();
+		let filename = tmp_dir.join(format!("matrix.{}", extension)).to_string_lossy().into_owned();
+
+		let mut sink = MatrixSink::new(&filename, format).unwrap();
+		sink.write_pairs(pairs).unwrap();
+		let truncated = sink.finish().unwrap();
+
+		let content = fs::read_to_string(&filename).unwrap();
+		let table = fs::read_to_string(format!("{}.taxa", filename)).ok();
+		fs::remove_dir_all(&tmp_dir).unwrap();
+		(content, truncated, table)
+	}
+
+	#[test]
+	fn paup_labels_are_quoted() {
+		let (content, _, _) = write_matrix(MatrixFormat::Paup(Coding::Unordered), &[pair(&NAMES, &[10, 10, 13, 13])], "nex");
+		assert!(content.contains("matrix\n\n\
+				'Homo sapiens chr1' A\n\
+				'Homo sapiens chr2' A\n\
+				'Mus'               B\n\
+				'O''Brien'          B\n;"));
+	}
+
+	#[test]
+	fn phylip_labels_are_listed_in_taxa_table() {
+		let (content, _, table) = write_matrix(MatrixFormat::Phylip, &[pair(&NAMES, &[10, 10, 13, 13])], "phy");
+		assert_eq!(content, "4 1\n\
+				Homo sap1 A\n\
+				Homo sap2 A\n\
+				Mus       B\n\
+				O'Brien   B");
+		assert_eq!(table.unwrap(), "Homo sap1\tHomo sapiens chr1\n\
+				Homo sap2\tHomo sapiens chr2\n\
+				Mus\tMus\n\
+				O'Brien\tO'Brien\n");
+	}
+
+	#[test]
+	fn ordered_coding_adds_typeset() {
+		let (content, _, _) = write_matrix(MatrixFormat::Paup(Coding::Ordered), &[pair(&NAMES, &[10, 10, 13, 15])], "nex");
+		assert!(content.contains("begin assumptions;\n\ttypeset * gaps = ord: all;\nend;\n"));
+	}
+
+	#[test]
+	fn step_matrices_are_shared_by_equal_relative_gap_sizes() {
+		let (content, _, _) = write_matrix(MatrixFormat::Paup(Coding::StepMatrix), &[
+			pair(&NAMES, &[10, 10, 13, 15]),
+			pair(&NAMES, &[10, 10, 10, 10]),
+			pair(&NAMES, &[20, 20, 23, 25]),
+			pair(&NAMES, &[10, 12, 12, 12])
+		], "nex");
+		assert!(content.contains("begin assumptions;\n\
+				\tusertype gaps1 (stepmatrix) = 3\n\
+				\t\tA B C\n\
+				\t\t[A] . 3 5\n\
+				\t\t[B] 3 . 2\n\
+				\t\t[C] 5 2 .\n\
+				\t;\n\
+				\tusertype gaps2 (stepmatrix) = 2\n\
+				\t\tA B\n\
+				\t\t[A] . 2\n\
+				\t\t[B] 2 .\n\
+				\t;\n\
+				\ttypeset * gaps = gaps1: 1, unord: 2, gaps1: 3, gaps2: 4;\n\
+				end;\n"));
+	}
+
+	#[test]
+	fn states_beyond_the_format_limit_are_missing() {
+		let names = ["s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8"];
+		let (content, truncated, _) = write_matrix(MatrixFormat::Phylip, &[
+			pair(&names, &[10, 11, 12, 13, 14, 15, 16, 17, 18]),
+			pair(&names, &[10, 10, 10, 10, 13, 13, 13, 13, 13])
+		], "phy");
+		assert_eq!(truncated, 1);
+		assert!(content.contains("\ns7        H B\n"));
+		assert!(content.ends_with("\ns8        ? B"));
+
+		// PAUP can represent all nine states
+		let (content, truncated, _) = write_matrix(MatrixFormat::Paup(Coding::Unordered), &[
+			pair(&names, &[10, 11, 12, 13, 14, 15, 16, 17, 18])
+		], "nex");
+		assert_eq!(truncated, 0);
+		assert!(content.contains("\n's8' I\n"));
+	}
+}
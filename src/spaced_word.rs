@@ -1,50 +1,57 @@
 use std::cmp::Ordering;
+use std::sync::Arc;
 
 #[derive(Debug, Eq, Clone)]
 pub struct SpacedWord {
-	pub seq_name: String,
+	// shared by all words of a sequence (see Sequence::name)
+	pub seq_name: Arc<str>,
+	// 0-based position of the first base on the forward strand, also for words on the reverse strand
+	// (i.e. the position of the last base of the word as it is read on the reverse strand)
 	pub position: i64,
-	pub match_positions: Option<u64>,
+	// number of bases (including don't care positions)
+	pub len: usize,
+	pub match_positions: Option<u128>,
 	pub rev_comp: bool
 }
 
 impl SpacedWord {
-	// Returns None, if the word contains a non-A/C/G/T symbol on a match position
-	pub fn new(seq_name: &str, mut position: i64, word: &Option<&str>, pattern: &Option<&str>, rev_comp: bool) -> Option<SpacedWord> {
-		if rev_comp {
-			position = -position;
+	pub fn new(seq_name: Arc<str>, position: i64, len: usize, rev_comp: bool) -> SpacedWord {
+		SpacedWord {
+			seq_name,
+			position,
+			len,
+			match_positions: None,
+			rev_comp
 		}
+	}
 
-		let mut match_positions = None;
-		if let (Some(word), Some(pattern)) = (word, pattern) {
-			match_positions = Some(SpacedWord::match_positions(word, pattern)?)
+	// Like new(), but with encoded match positions (see Sequence::spaced_words)
+	pub fn from_key(seq_name: Arc<str>, position: i64, len: usize, key: u128, rev_comp: bool) -> SpacedWord {
+		SpacedWord {
+			match_positions: Some(key),
+			..SpacedWord::new(seq_name, position, len, rev_comp)
 		}
-
-		Some(SpacedWord {
-			seq_name: String::from(seq_name),
-			position,
-			match_positions,
-			rev_comp
-		})
 	}
 
-	// Returns None, if the word contains a non-A/C/G/T symbol on a match position
-	fn match_positions(word: &str, pattern: &str) -> Option<u64> {
-		word.chars().zip(pattern.chars())
-			.filter(|(_, p)| p == &'1')
-			.fold(Some(0), |acc, (base, _)| {
-				match base {
-					'A' | 'a' => Some(acc? + 0 << 2),
-					'C' | 'c' => Some(acc? + 1 << 2),
-					'G' | 'g' => Some(acc? + 2 << 2),
-					'T' | 't' => Some(acc? + 3 << 2),
-					_ => None
-				}
-			})
+	/// Position of the first base on the strand of the word (seq_len is the length of the sequence)
+	pub fn strand_position(&self, seq_len: usize) -> i64 {
+		if self.rev_comp {
+			seq_len as i64 - self.position - self.len as i64
+		}
+		else {
+			self.position
+		}
 	}
 
-	pub fn gap_size(&self, other: &SpacedWord) -> i64 {
-		(self.position as i64 - other.position as i64).abs()
+	/// Distance between the starts of this and the other word, measured on the strand of this word
+	/// (positive, if the other word is downstream)
+	pub fn gap(&self, other: &SpacedWord) -> i64 {
+		if self.rev_comp {
+			(self.position + self.len as i64) - (other.position + other.len as i64)
+		}
+		else {
+			other.position - self.position
+		}
 	}
 }
 
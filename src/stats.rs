@@ -1,4 +1,5 @@
 use std::fmt;
+use std::collections::{HashMap, HashSet};
 use crate::PBlock;
 
 #[derive(Debug, Clone)]
@@ -13,41 +14,93 @@ pub struct Stats {
     // coverage
     pub coverage_perc: f64,
     // RF-distance (use -1 if unknown)
-    pub rfdist: i64
+    pub rfdist: i64,
+    // number of characters with states that couldn't be represented in the output format
+    pub truncated_columns: usize,
+    // number of input blocks whose words don't match the sequences (see BlockReader::verify)
+    pub mismatched_blocks: usize,
+    // number of input blocks that were dropped because of their score (see BlockReader::min_score)
+    pub low_score_blocks: usize,
+    // number of loci with pairs that support different topologies (see locus::resolve_conflicts)
+    pub conflicting_loci: usize,
+    // number of input sequences, index of each taxon that occurred in a pair and covered quartets as
+    // sorted taxon indices (for the coverage)
+    seq_num: usize,
+    taxa: HashMap<String, u32>,
+    quartets: HashSet<[u32; 4]>
 }
 
 impl Stats {
-    pub fn new(pairs: &Vec<(PBlock, PBlock)>, seq_num: usize) -> Stats {
-        let total_pairs = pairs.len();
+    pub fn new(pairs: &[(PBlock, PBlock)], seq_num: usize) -> Stats {
+        let mut stats = Stats {
+            total_pairs: 0,
+            strong_pairs: 0,
+            weak_pairs: 0,
+            correct_perc: -1.0,
+            coverage_perc: 0.0,
+            rfdist: -1,
+            truncated_columns: 0,
+            mismatched_blocks: 0,
+            low_score_blocks: 0,
+            conflicting_loci: 0,
+            seq_num,
+            taxa: HashMap::new(),
+            quartets: HashSet::new()
+        };
+        stats.add_pairs(pairs);
+        stats
+    }
+
+    /// Adds further pairs to the statistics (if the pairs are processed in chunks)
+    pub fn add_pairs(&mut self, pairs: &[(PBlock, PBlock)]) {
         let strong_pairs = pairs.iter()
             .filter(|(a, b)| PBlock::strong_pair(a, b))
             .count();
-        let weak_pairs = total_pairs - strong_pairs;
-        let correct_perc = -1.0;
-        let rfdist = -1;
+        self.total_pairs += pairs.len();
+        self.strong_pairs += strong_pairs;
+        self.weak_pairs += pairs.len() - strong_pairs;
+
+        // Pairs of larger blocks cover all quartets they can be decomposed into
+        for (a, b) in pairs {
+            self.add_quartets(a, b);
+        }
+
+        let n = self.seq_num;
+        let max_coverage = (n*(n-1)*(n-2)*(n-3)) as f64 / 24.0;
+        self.coverage_perc = self.quartets.len() as f64 / max_coverage * 100.0;
+    }
 
-        let max_coverage = (seq_num*(seq_num-1)*(seq_num-2)*(seq_num-3)) as f64 / 24.0;
-        let mut unique_pairs = pairs.clone();
-        unique_pairs.sort_unstable_by(|a, b| {
-            a.0.get_sequence_names().cmp(&b.0.get_sequence_names())
-        });
-        unique_pairs.dedup_by(|a, b| {
-            a.0.get_sequence_names() == b.0.get_sequence_names()
-        });
-        let coverage_perc = unique_pairs.len() as f64 / max_coverage * 100.0;
+    // Adds the informative quartets of a pair (see PBlock::informative_pair) without building their blocks
+    fn add_quartets(&mut self, a: &PBlock, b: &PBlock) {
+        let d = PBlock::distances(a, b);
+        let taxa = a.words.iter()
+            .map(|word| {
+                let next = self.taxa.len() as u32;
+                *self.taxa.entry(word.seq_name.to_string()).or_insert(next)
+            })
+            .collect::<Vec<u32>>();
 
-        Stats {
-            total_pairs,
-            strong_pairs,
-            weak_pairs,
-            correct_perc,
-            coverage_perc,
-            rfdist
+        let n = a.len();
+        for i in 0..n {
+            for j in i+1..n {
+                for k in j+1..n {
+                    for l in k+1..n {
+                        let gaps = [d[i], d[j], d[k], d[l]];
+                        // A quartet supports a topology if exactly two of its gap sizes are equal
+                        if gaps.iter().any(|x| gaps.iter().filter(|y| *y == x).count() == 2) {
+                            let mut quartet = [taxa[i], taxa[j], taxa[k], taxa[l]];
+                            quartet.sort_unstable();
+                            self.quartets.insert(quartet);
+                        }
+                    }
+                }
+            }
         }
     }
+
     pub fn stats_to_csv(stats: &[Stats], separator: &str) -> String {
         let header = ["pairs", "pairs_22", "pairs_22_perc",
-            "pairs_211", "pairs_211_perc", "correct_perc", "coverage", "rfdist"];
+            "pairs_211", "pairs_211_perc", "correct_perc", "coverage", "rfdist", "truncated", "mismatched", "low_score", "conflicts"];
 
         let mut content = header.join(separator);
         for stat in stats {
@@ -66,7 +119,11 @@ impl Stats {
             (self.weak_pairs as f64 / self.total_pairs as f64 * 100.0).to_string(),
             self.correct_perc.to_string(),
             self.coverage_perc.to_string(),
-            self.rfdist.to_string()
+            self.rfdist.to_string(),
+            self.truncated_columns.to_string(),
+            self.mismatched_blocks.to_string(),
+            self.low_score_blocks.to_string(),
+            self.conflicting_loci.to_string()
         ];
 
         values.join(separator)
@@ -81,6 +138,18 @@ impl fmt::Display for Stats {
         s = format!("{}Strong pairs: \t{} \t({:.2}%)\n", s, self.strong_pairs, self.strong_pairs as f64 / self.total_pairs as f64 * 100.0);
         s = format!("{}Weak pairs: \t{} \t({:.2}%)\n", s, self.weak_pairs, self.weak_pairs as f64 / self.total_pairs as f64 * 100.0);
         s = format!("{}Coverage: \t{:.2}%\n", s, self.coverage_perc);
+        if self.truncated_columns > 0 {
+            s = format!("{}Truncated: \t{} \t({:.2}%)\n", s, self.truncated_columns, self.truncated_columns as f64 / self.total_pairs as f64 * 100.0);
+        }
+        if self.mismatched_blocks > 0 {
+            s = format!("{}Mismatched: \t{} input blocks\n", s, self.mismatched_blocks);
+        }
+        if self.low_score_blocks > 0 {
+            s = format!("{}Low score: \t{} input blocks\n", s, self.low_score_blocks);
+        }
+        if self.conflicting_loci > 0 {
+            s = format!("{}Conflicts: \t{} loci\n", s, self.conflicting_loci);
+        }
         s = format!("{}=============================================", s);
 
         write!(f, "{}", s)
@@ -1,3 +1,5 @@
+use crate::newick::{self, Tree};
+use crate::GapsError;
 use std::env;
 use std::{fs, str};
 use std::fs::File;
@@ -5,46 +7,70 @@ use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::io::{Write, BufRead, BufReader};
 
-pub fn create_tmp_folder() -> PathBuf {
+pub fn create_tmp_folder() -> Result<PathBuf, GapsError> {
 	let mut tmp_dir = env::temp_dir();
 	let r: u64 = rand::random();
 	tmp_dir.push(format!("gaps_rs_{}", r));
-	fs::create_dir(&tmp_dir).unwrap();
-	tmp_dir
+	fs::create_dir(&tmp_dir).map_err(|e| GapsError::io(&tmp_dir.to_string_lossy(), e))?;
+	Ok(tmp_dir)
 }
 
 // Requires single_quartet_check to be in path
-pub fn qcheck(qtreefile: &str, fastafile: &str, nwkfile: &str) -> (u64, u64) {
+pub fn qcheck(qtreefile: &str, fastafile: &str, nwkfile: &str) -> Result<(u64, u64), GapsError> {
 	let output = Command::new("single_quartet_check")
 		.arg(fastafile)
 		.arg(qtreefile)
 		.arg(nwkfile)
 		.output()
-		.expect("Failed to execute qcheck");
-
-	let lines = str::from_utf8(&output.stdout).unwrap()
-		.split('\n').collect::<Vec<_>>();
-
-	lines[0..lines.len()-1].iter()
-		.fold((0, 0), |acc, line| {
-			if line == &"0" {
-				return (acc.0 + 1, acc.1);
-			}
-			if line == &"1" {
-				return (acc.0, acc.1 + 1);
-			}
-			panic!("Invalid qcheck output")
-		})
+		.map_err(|e| GapsError::external_tool("single_quartet_check", &e.to_string()))?;
+
+	let lines = String::from_utf8_lossy(&output.stdout).into_owned();
+	let lines = lines.split('\n').collect::<Vec<_>>();
+
+	let mut result = (0, 0);
+	for line in &lines[0..lines.len()-1] {
+		match *line {
+			"0" => result.0 += 1,
+			"1" => result.1 += 1,
+			_ => return Err(GapsError::external_tool("single_quartet_check", &format!("Invalid output \"{}\"", line)))
+		}
+	}
+	Ok(result)
+}
+
+// Returns the Robinson-Foulds distance between the first two trees of the input file
+pub fn rfdist(infile: &str) -> Result<u64, GapsError> {
+	let (a, b) = read_tree_pair(infile)?;
+	Ok(newick::rf_distance(&a, &b).map_err(|e| e.in_file(infile, None))? as u64)
+}
+
+// Same as rfdist, but divided by the maximum possible distance
+pub fn rfdist_normalized(infile: &str) -> Result<f64, GapsError> {
+	let (a, b) = read_tree_pair(infile)?;
+	newick::rf_distance_normalized(&a, &b).map_err(|e| e.in_file(infile, None))
 }
 
-pub fn rfdist(infile: &str) -> u64 {
+fn read_tree_pair(infile: &str) -> Result<(Tree, Tree), GapsError> {
+	let content = fs::read_to_string(infile).map_err(|e| GapsError::io(infile, e))?;
+	let mut trees = Tree::parse_all(&content).map_err(|e| e.in_file(infile, None))?.into_iter();
+
+	match (trees.next(), trees.next()) {
+		(Some(a), Some(b)) => Ok((a, b)),
+		_ => Err(GapsError::parse(infile, None, "Input file has to contain two trees"))
+	}
+}
+
+// Requires phylip treedist to be in path (only used to cross-check rfdist)
+pub fn rfdist_phylip(infile: &str) -> Result<u64, GapsError> {
+	let treedist_error = |msg: &str| GapsError::external_tool("treedist", msg);
+
 	// Create temporary folder
-	let tmp_folder = create_tmp_folder();
+	let tmp_folder = create_tmp_folder()?;
 
 	// Copy input file to intree
 	let mut intree_file = tmp_folder.clone();
 	intree_file.push("intree");
-	fs::copy(infile, intree_file).unwrap();
+	fs::copy(infile, intree_file).map_err(|e| GapsError::io(infile, e))?;
 
 	// Execute phylip treedist
 	let mut child = Command::new("treedist")
@@ -52,28 +78,29 @@ pub fn rfdist(infile: &str) -> u64 {
 		.current_dir(&tmp_folder)
 		.stdin(Stdio::piped())
 		.stdout(Stdio::piped())
-		.spawn().unwrap();
+		.spawn().map_err(|e| treedist_error(&e.to_string()))?;
 
 	// Write commands to stdin
-	let child_stdin = child.stdin.as_mut().unwrap();
-	child_stdin.write_all(b"D\nY\n").unwrap();
-	child.wait_with_output().unwrap();
+	let child_stdin = child.stdin.as_mut().ok_or_else(|| treedist_error("Unable to open stdin"))?;
+	child_stdin.write_all(b"D\nY\n").map_err(|e| treedist_error(&e.to_string()))?;
+	child.wait_with_output().map_err(|e| treedist_error(&e.to_string()))?;
 
 	// Extract result
 	let mut outfile = tmp_folder.clone();
 	outfile.push("outfile");
 
-	let lines = BufReader::new(File::open(outfile).expect("Unable to open file")).lines();
-	let last_line = lines.last().unwrap().unwrap();
+	let file = File::open(&outfile).map_err(|e| GapsError::io(&outfile.to_string_lossy(), e))?;
+	let last_line = BufReader::new(file).lines()
+		.last()
+		.ok_or_else(|| treedist_error("Empty outfile"))?
+		.map_err(|e| GapsError::io(&outfile.to_string_lossy(), e))?;
 	let result = last_line.split(' ')
-		.collect::<Vec<&str>>()
 		.last()
-		.unwrap()
-		.parse::<u64>()
-		.unwrap();
+		.and_then(|dist| dist.parse::<u64>().ok())
+		.ok_or_else(|| treedist_error(&format!("Invalid output \"{}\"", last_line)))?;
 
 	// Delete temporary folder
-	fs::remove_dir_all(tmp_folder).unwrap();
+	fs::remove_dir_all(&tmp_folder).map_err(|e| GapsError::io(&tmp_folder.to_string_lossy(), e))?;
 
-	result
-}
\ No newline at end of file
+	Ok(result)
+}